@@ -0,0 +1,235 @@
+use clap::*;
+use texture_atlas::*;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::Instant;
+
+mod workload;
+
+use crate::workload::{Op, Workload, WorkloadKind};
+
+struct BenchResult {
+    workload: &'static str,
+    allocator: &'static str,
+    allocations: usize,
+    failed_allocations: usize,
+    deallocations: usize,
+    /// Average time spent in `allocate`, in nanoseconds.
+    allocate_ns: f64,
+    /// Average time spent in `deallocate`, in nanoseconds.
+    deallocate_ns: f64,
+    peak_textures: usize,
+    /// Ratio between the peak requested area and the area of the peak number of textures.
+    packing_efficiency: f64,
+}
+
+fn main() {
+    let matches = App::new("Texture atlas allocator benchmarks")
+        .version("0.1")
+        .author("Nicolas Silva <nical@fastmail.com>")
+        .about("Compare the texture atlas allocators on synthetic workloads.")
+        .arg(Arg::with_name("COUNT")
+            .short("n")
+            .long("count")
+            .help("Number of allocations per workload.")
+            .value_name("COUNT")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("SEED")
+            .long("seed")
+            .help("Seed of the random number generator.")
+            .value_name("SEED")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("SIZE")
+            .short("s")
+            .long("size")
+            .help("Width and height of the textures.")
+            .value_name("SIZE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("WORKLOAD")
+            .short("w")
+            .long("workload")
+            .help("Only run the specified workload (uniform-glyphs, zipf-glyphs, mixed-ui or churn).")
+            .value_name("WORKLOAD")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("CSV_OUTPUT")
+            .long("csv")
+            .help("Write the results in a CSV file")
+            .value_name("CSV_OUTPUT")
+            .takes_value(true)
+            .required(false)
+        )
+        .get_matches();
+
+    let count = matches.value_of("COUNT")
+        .map(|s| s.parse::<usize>().expect("Invalid allocation count."))
+        .unwrap_or(10_000);
+    let seed = matches.value_of("SEED")
+        .map(|s| s.parse::<u64>().expect("Invalid seed."))
+        .unwrap_or(1);
+    let size = matches.value_of("SIZE")
+        .map(|s| s.parse::<i32>().expect("Invalid texture size."))
+        .unwrap_or(2048);
+    let size = size2(size, size);
+
+    let kinds: Vec<WorkloadKind> = match matches.value_of("WORKLOAD") {
+        Some(name) => {
+            let kind = WorkloadKind::ALL.iter()
+                .find(|kind| kind.name() == name)
+                .expect("Unknown workload.");
+            vec![*kind]
+        }
+        None => WorkloadKind::ALL.to_vec(),
+    };
+
+    let mut results = Vec::new();
+    for &kind in &kinds {
+        let workload = Workload::generate(kind, count, seed);
+
+        results.push(run::<GuillotineAllocator>(
+            "guillotine",
+            size,
+            guillotiere::DEFAULT_OPTIONS,
+            &workload,
+        ));
+        results.push(run::<ShelfAllocator>(
+            "shelf",
+            size,
+            etagere::AllocatorOptions::DEFAULT,
+            &workload,
+        ));
+        results.push(run::<TiledAllocator>(
+            "tiled",
+            size,
+            tiled::TiledAllocatorConfig::single_layer(tiled::TileSizes::WrDefault, size2(512, 512)),
+            &workload,
+        ));
+    }
+
+    print_table(&results);
+
+    if let Some(file_name) = matches.value_of("CSV_OUTPUT") {
+        let mut file = File::create(file_name).expect("Failed to open the CSV file.");
+        write_csv(&results, &mut file).expect("Failed to write the CSV file.");
+    }
+}
+
+fn run<A: AtlasAllocator>(name: &'static str, size: Size, config: A::Config, workload: &Workload) -> BenchResult {
+    let mut atlas = AllocatorList::<A>::new(size, config);
+    let texture_area = size.area() as f64;
+
+    let mut handles: Vec<Option<(Handle, i32)>> = Vec::new();
+    let mut allocate_time = 0;
+    let mut deallocate_time = 0;
+    let mut deallocations = 0;
+    let mut failed_allocations = 0;
+    let mut live_area: i64 = 0;
+    let mut peak_live_area: i64 = 0;
+    let mut peak_textures = 0;
+
+    for op in &workload.ops {
+        match *op {
+            Op::Allocate(size) => {
+                let t0 = Instant::now();
+                let alloc = atlas.allocate(size);
+                allocate_time += t0.elapsed().as_nanos();
+
+                if alloc.is_none() {
+                    failed_allocations += 1;
+                }
+
+                let area = size.area();
+                handles.push(alloc.map(|alloc| (alloc.handle, area)));
+                if alloc.is_some() {
+                    live_area += area as i64;
+                }
+            }
+            Op::Deallocate(idx) => {
+                if let Some((handle, area)) = handles[idx].take() {
+                    let t0 = Instant::now();
+                    atlas.deallocate(handle);
+                    deallocate_time += t0.elapsed().as_nanos();
+
+                    deallocations += 1;
+                    live_area -= area as i64;
+                }
+            }
+        }
+
+        peak_live_area = peak_live_area.max(live_area);
+        peak_textures = peak_textures.max(atlas.num_textures());
+    }
+
+    let allocations = handles.len();
+
+    BenchResult {
+        workload: workload.kind.name(),
+        allocator: name,
+        allocations,
+        failed_allocations,
+        deallocations,
+        allocate_ns: allocate_time as f64 / allocations.max(1) as f64,
+        deallocate_ns: deallocate_time as f64 / deallocations.max(1) as f64,
+        peak_textures,
+        packing_efficiency: if peak_textures > 0 {
+            peak_live_area as f64 / (peak_textures as f64 * texture_area)
+        } else {
+            0.0
+        },
+    }
+}
+
+fn print_table(results: &[BenchResult]) {
+    println!(
+        "{:<16} {:<12} {:>8} {:>8} {:>10} {:>12} {:>14} {:>10} {:>10}",
+        "workload", "allocator", "allocs", "failed", "deallocs",
+        "alloc (ns)", "dealloc (ns)", "textures", "packing",
+    );
+    println!("{:-<16} {:-<12} {:->8} {:->8} {:->10} {:->12} {:->14} {:->10} {:->10}", "", "", "", "", "", "", "", "", "");
+    for r in results {
+        println!(
+            "{:<16} {:<12} {:>8} {:>8} {:>10} {:>12.1} {:>14.1} {:>10} {:>9.1}%",
+            r.workload,
+            r.allocator,
+            r.allocations,
+            r.failed_allocations,
+            r.deallocations,
+            r.allocate_ns,
+            r.deallocate_ns,
+            r.peak_textures,
+            r.packing_efficiency * 100.0,
+        );
+    }
+}
+
+fn write_csv(results: &[BenchResult], output: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(
+        output,
+        "workload,allocator,allocations,failed_allocations,deallocations,allocate_ns,deallocate_ns,peak_textures,packing_efficiency"
+    )?;
+    for r in results {
+        writeln!(
+            output,
+            "{},{},{},{},{},{:.2},{:.2},{},{:.4}",
+            r.workload,
+            r.allocator,
+            r.allocations,
+            r.failed_allocations,
+            r.deallocations,
+            r.allocate_ns,
+            r.deallocate_ns,
+            r.peak_textures,
+            r.packing_efficiency,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use texture_atlas::{Size, size2};
+
+/// A small xorshift64* generator so that workloads are reproducible from a seed
+/// without pulling in a dependency.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Rng { state: (seed ^ 0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a value in [min, max] (inclusive).
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        debug_assert!(min <= max);
+        let span = (max - min) as u64 + 1;
+        min + (self.next_u64() % span) as i32
+    }
+
+    /// Returns a value in [0, 1).
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Op {
+    Allocate(Size),
+    /// Deallocate the rectangle created by the n-th allocation of the workload.
+    Deallocate(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WorkloadKind {
+    /// Glyphs of uniformly distributed sizes.
+    UniformGlyphs,
+    /// Glyphs whose sizes follow a Zipf distribution: a few font sizes dominate.
+    ZipfGlyphs,
+    /// A mix of small icons, medium images and a few large ones.
+    MixedUi,
+    /// A cache that continuously evicts old items to make room for new ones.
+    Churn,
+}
+
+impl WorkloadKind {
+    pub const ALL: &'static [WorkloadKind] = &[
+        WorkloadKind::UniformGlyphs,
+        WorkloadKind::ZipfGlyphs,
+        WorkloadKind::MixedUi,
+        WorkloadKind::Churn,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WorkloadKind::UniformGlyphs => "uniform-glyphs",
+            WorkloadKind::ZipfGlyphs => "zipf-glyphs",
+            WorkloadKind::MixedUi => "mixed-ui",
+            WorkloadKind::Churn => "churn",
+        }
+    }
+}
+
+pub struct Workload {
+    pub kind: WorkloadKind,
+    pub ops: Vec<Op>,
+}
+
+impl Workload {
+    /// Generate `count` allocations, interleaved with deallocations depending on the kind
+    /// of workload.
+    pub fn generate(kind: WorkloadKind, count: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let ops = match kind {
+            WorkloadKind::UniformGlyphs => glyphs(&mut rng, count, &mut |rng| {
+                size2(rng.range(4, 32), rng.range(8, 40))
+            }),
+            WorkloadKind::ZipfGlyphs => {
+                let zipf = Zipf::new(48, 1.1);
+                glyphs(&mut rng, count, &mut |rng| {
+                    // Low ranks (small font sizes) are the most common.
+                    let font_size = 8 + 2 * zipf.sample(rng) as i32;
+                    size2(
+                        rng.range(font_size / 4, font_size),
+                        rng.range(font_size * 3 / 4, font_size + font_size / 4),
+                    )
+                })
+            }
+            WorkloadKind::MixedUi => mixed_ui(&mut rng, count),
+            WorkloadKind::Churn => churn(&mut rng, count),
+        };
+
+        Workload { kind, ops }
+    }
+}
+
+/// Glyphs are rarely evicted: allocate everything and only release a small fraction
+/// of the items at the end.
+fn glyphs(rng: &mut Rng, count: usize, size: &mut dyn FnMut(&mut Rng) -> Size) -> Vec<Op> {
+    let mut ops = Vec::with_capacity(count + count / 10);
+    for _ in 0..count {
+        ops.push(Op::Allocate(size(rng)));
+    }
+    for idx in 0..count {
+        if rng.unit() < 0.1 {
+            ops.push(Op::Deallocate(idx));
+        }
+    }
+
+    ops
+}
+
+fn mixed_ui(rng: &mut Rng, count: usize) -> Vec<Op> {
+    let mut ops = Vec::with_capacity(count * 2);
+    let mut live = Vec::new();
+    for idx in 0..count {
+        let p = rng.unit();
+        let size = if p < 0.7 {
+            // Icons.
+            let s = rng.range(16, 64);
+            size2(s, s)
+        } else if p < 0.97 {
+            // Images, buttons, etc.
+            size2(rng.range(32, 256), rng.range(32, 256))
+        } else {
+            size2(rng.range(256, 512), rng.range(256, 512))
+        };
+
+        ops.push(Op::Allocate(size));
+        live.push(idx);
+
+        // Occasionally navigate away and drop a few items.
+        if rng.unit() < 0.3 && !live.is_empty() {
+            let i = rng.range(0, live.len() as i32 - 1) as usize;
+            ops.push(Op::Deallocate(live.swap_remove(i)));
+        }
+    }
+
+    ops
+}
+
+fn churn(rng: &mut Rng, count: usize) -> Vec<Op> {
+    let mut ops = Vec::with_capacity(count * 2);
+    // Keep roughly this many items alive, evicting the oldest ones first.
+    let budget = (count / 8).max(1);
+    let mut live = std::collections::VecDeque::new();
+    for idx in 0..count {
+        let size = size2(rng.range(8, 128), rng.range(8, 128));
+        ops.push(Op::Allocate(size));
+        live.push_back(idx);
+
+        while live.len() > budget {
+            // Mostly evict the oldest item, sometimes a random one.
+            let victim = if rng.unit() < 0.8 {
+                live.pop_front().unwrap()
+            } else {
+                let i = rng.range(0, live.len() as i32 - 1) as usize;
+                live.remove(i).unwrap()
+            };
+            ops.push(Op::Deallocate(victim));
+        }
+    }
+
+    ops
+}
+
+struct Zipf {
+    cumulative: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, exponent: f64) -> Self {
+        let mut cumulative = Vec::with_capacity(n);
+        let mut sum = 0.0;
+        for rank in 1..=n {
+            sum += 1.0 / (rank as f64).powf(exponent);
+            cumulative.push(sum);
+        }
+        for value in &mut cumulative {
+            *value /= sum;
+        }
+
+        Zipf { cumulative }
+    }
+
+    /// Returns a rank in [0, n).
+    fn sample(&self, rng: &mut Rng) -> usize {
+        let p = rng.unit();
+        match self.cumulative.binary_search_by(|v| v.partial_cmp(&p).unwrap()) {
+            Ok(idx) | Err(idx) => idx.min(self.cumulative.len() - 1),
+        }
+    }
+}
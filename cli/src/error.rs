@@ -0,0 +1,79 @@
+//! Errors reported by the command-line interface.
+
+use crate::json::Json;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A missing or malformed argument or config option. Exit code 2.
+    InvalidArgument,
+    /// Reading or writing a file failed. Exit code 3.
+    Io,
+    /// The atlas file could not be parsed. Exit code 4.
+    InvalidAtlas,
+    /// No allocation has the requested name. Exit code 5.
+    UnknownAllocation,
+    /// The allocator could not fit the requested rectangle. Exit code 6.
+    AllocationFailed,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::InvalidArgument => "invalid_argument",
+            ErrorKind::Io => "io",
+            ErrorKind::InvalidAtlas => "invalid_atlas",
+            ErrorKind::UnknownAllocation => "unknown_allocation",
+            ErrorKind::AllocationFailed => "allocation_failed",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::InvalidArgument => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::InvalidAtlas => 4,
+            ErrorKind::UnknownAllocation => 5,
+            ErrorKind::AllocationFailed => 6,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+pub type CliResult<T> = std::result::Result<T, CliError>;
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        CliError { kind, message: message.into() }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        CliError::new(ErrorKind::InvalidArgument, message)
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("error", Json::Object(vec![
+                ("kind", self.kind.name().into()),
+                ("message", self.message.as_str().into()),
+            ])),
+        ])
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(err: std::io::Error) -> Self {
+        CliError::new(ErrorKind::Io, err.to_string())
+    }
+}
@@ -0,0 +1,257 @@
+//! Readers and writers for the dependency-free image formats used by `atlas pack`.
+
+use crate::error::{CliError, CliResult, ErrorKind};
+use std::convert::TryFrom;
+use texture_atlas::{size2, Size};
+
+/// An image with four bytes per pixel in RGBA order, rows from top to bottom.
+pub struct Image {
+    pub size: Size,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(size: Size) -> Self {
+        Image {
+            size,
+            pixels: vec![0; size.width as usize * size.height as usize * 4],
+        }
+    }
+
+    /// Copy the pixels of another image at the provided position. The image must fit.
+    pub fn blit(&mut self, src: &Image, x: i32, y: i32) {
+        assert!(x >= 0 && y >= 0 && x + src.size.width <= self.size.width && y + src.size.height <= self.size.height);
+
+        let row_len = src.size.width as usize * 4;
+        for row in 0..src.size.height {
+            let src_offset = row as usize * row_len;
+            let dst_offset = ((y + row) as usize * self.size.width as usize + x as usize) * 4;
+            self.pixels[dst_offset..dst_offset + row_len].copy_from_slice(&src.pixels[src_offset..src_offset + row_len]);
+        }
+    }
+
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels.chunks(4).flat_map(|p| p[..3].iter().cloned()).collect()
+    }
+}
+
+/// The number of bytes of an image, if it fits in memory.
+fn byte_count(w: u32, h: u32, bytes_per_pixel: usize) -> Option<usize> {
+    let count = (w as u64).checked_mul(h as u64)?.checked_mul(bytes_per_pixel as u64)?;
+    usize::try_from(count).ok()
+}
+
+fn invalid(file_name: &str, message: &str) -> CliError {
+    CliError::new(ErrorKind::InvalidArgument, format!("{}: {}", file_name, message))
+}
+
+/// Read a binary PPM (P6), binary PGM (P5) or TGA image.
+pub fn read_image(file_name: &str) -> CliResult<Image> {
+    let bytes = std::fs::read(file_name)
+        .map_err(|e| CliError::new(ErrorKind::Io, format!("Failed to read {}: {}", file_name, e)))?;
+
+    if bytes.starts_with(b"P6") || bytes.starts_with(b"P5") {
+        read_pnm(&bytes).map_err(|message| invalid(file_name, message))
+    } else if file_name.to_lowercase().ends_with(".tga") {
+        read_tga(&bytes).map_err(|message| invalid(file_name, message))
+    } else {
+        Err(invalid(file_name, "unsupported image format, expected PPM, PGM or TGA"))
+    }
+}
+
+pub fn read_pnm(bytes: &[u8]) -> Result<Image, &'static str> {
+    let gray = &bytes[..2] == b"P5";
+    let mut pos = 2;
+    let mut header = [0u32; 3];
+    for value in &mut header {
+        // Skip whitespace and comments.
+        loop {
+            match bytes.get(pos) {
+                Some(b'#') => {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => pos += 1,
+                Some(_) => break,
+                None => return Err("truncated header"),
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        *value = std::str::from_utf8(&bytes[start..pos])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or("invalid header")?;
+    }
+    // A single whitespace character separates the header from the pixels.
+    pos += 1;
+
+    let [w, h, max_value] = header;
+    if max_value == 0 || max_value > 255 {
+        return Err("only 8 bit images are supported");
+    }
+    if w == 0 || h == 0 || w > i32::MAX as u32 || h > i32::MAX as u32 {
+        return Err("invalid size");
+    }
+
+    let size = size2(w as i32, h as i32);
+    let channels = if gray { 1 } else { 3 };
+    let len = byte_count(w, h, 4).and(byte_count(w, h, channels)).ok_or("image too large")?;
+    let data = bytes.get(pos..).and_then(|data| data.get(..len)).ok_or("truncated pixel data")?;
+    let scale = |v: u8| (v as u32 * 255 / max_value) as u8;
+
+    let mut image = Image::new(size);
+    for (dst, src) in image.pixels.chunks_mut(4).zip(data.chunks(channels)) {
+        let (r, g, b) = if gray {
+            (src[0], src[0], src[0])
+        } else {
+            (src[0], src[1], src[2])
+        };
+        dst.copy_from_slice(&[scale(r), scale(g), scale(b), 255]);
+    }
+
+    Ok(image)
+}
+
+/// Read uncompressed or run-length encoded true-color and grayscale TGA images.
+pub fn read_tga(bytes: &[u8]) -> Result<Image, &'static str> {
+    if bytes.len() < 18 {
+        return Err("truncated header");
+    }
+
+    let id_length = bytes[0] as usize;
+    let color_map_type = bytes[1];
+    let image_type = bytes[2];
+    let w = u16::from_le_bytes([bytes[12], bytes[13]]) as i32;
+    let h = u16::from_le_bytes([bytes[14], bytes[15]]) as i32;
+    let depth = bytes[16];
+    let top_to_bottom = bytes[17] & 0x20 != 0;
+
+    if color_map_type != 0 {
+        return Err("color-mapped images are not supported");
+    }
+    let (gray, rle) = match image_type {
+        2 => (false, false),
+        3 => (true, false),
+        10 => (false, true),
+        11 => (true, true),
+        _ => return Err("unsupported image type"),
+    };
+    let bpp = match (gray, depth) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => return Err("unsupported pixel depth"),
+    };
+    if w == 0 || h == 0 {
+        return Err("invalid size");
+    }
+
+    // Check that the decoded image fits in memory before trusting the header.
+    byte_count(w as u32, h as u32, 4).ok_or("image too large")?;
+    let num_pixels = w as usize * h as usize;
+    let mut data = &bytes[(18 + id_length).min(bytes.len())..];
+    // Run-length encoded data can be smaller than the image, don't trust the header for the capacity.
+    let mut raw = Vec::with_capacity((num_pixels * bpp).min(data.len()));
+    if rle {
+        while raw.len() < num_pixels * bpp {
+            let (&packet, rest) = data.split_first().ok_or("truncated pixel data")?;
+            let count = (packet & 0x7f) as usize + 1;
+            if packet & 0x80 != 0 {
+                let pixel = rest.get(..bpp).ok_or("truncated pixel data")?;
+                for _ in 0..count {
+                    raw.extend_from_slice(pixel);
+                }
+                data = &rest[bpp..];
+            } else {
+                let pixels = rest.get(..bpp * count).ok_or("truncated pixel data")?;
+                raw.extend_from_slice(pixels);
+                data = &rest[bpp * count..];
+            }
+        }
+        raw.truncate(num_pixels * bpp);
+    } else {
+        raw.extend_from_slice(data.get(..num_pixels * bpp).ok_or("truncated pixel data")?);
+    }
+
+    let mut image = Image::new(size2(w, h));
+    let (w, h) = (w as usize, h as usize);
+    for y in 0..h {
+        let src_row = if top_to_bottom { y } else { h - 1 - y };
+        for x in 0..w {
+            let src = &raw[(src_row * w + x) * bpp..][..bpp];
+            let rgba = match bpp {
+                1 => [src[0], src[0], src[0], 255],
+                3 => [src[2], src[1], src[0], 255],
+                _ => [src[2], src[1], src[0], src[3]],
+            };
+            let dst = (y * w + x) * 4;
+            image.pixels[dst..dst + 4].copy_from_slice(&rgba);
+        }
+    }
+
+    Ok(image)
+}
+
+/// Write an uncompressed 32 bits TGA image.
+///
+/// The format can't store images wider or taller than 65535 pixels.
+pub fn write_tga(image: &Image, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    let side = |v: i32| u16::try_from(v).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("TGA images can't be larger than 65535x65535, got {}x{}", image.size.width, image.size.height))
+    });
+
+    let mut header = [0u8; 18];
+    header[2] = 2;
+    header[12..14].copy_from_slice(&side(image.size.width)?.to_le_bytes());
+    header[14..16].copy_from_slice(&side(image.size.height)?.to_le_bytes());
+    header[16] = 32;
+    // Top-to-bottom rows with 8 bits of alpha.
+    header[17] = 0x28;
+    output.write_all(&header)?;
+
+    let bgra: Vec<u8> = image.pixels.chunks(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect();
+    output.write_all(&bgra)
+}
+
+#[test]
+fn round_trip() {
+    let mut image = Image::new(size2(3, 2));
+    for (idx, byte) in image.pixels.iter_mut().enumerate() {
+        *byte = if idx % 4 == 3 { 255 } else { idx as u8 * 10 };
+    }
+
+    let mut tga = Vec::new();
+    write_tga(&image, &mut tga).unwrap();
+    let decoded = read_tga(&tga).unwrap();
+    assert_eq!(decoded.size, image.size);
+    assert_eq!(decoded.pixels, image.pixels);
+
+    let mut ppm = Vec::new();
+    texture_atlas::raster::write_ppm(image.size, &image.to_rgb(), &mut ppm).unwrap();
+    let decoded = read_pnm(&ppm).unwrap();
+    assert_eq!(decoded.pixels, image.pixels);
+
+    let mut blitted = Image::new(size2(5, 5));
+    blitted.blit(&image, 2, 3);
+    assert_eq!(&blitted.pixels[(3 * 5 + 2) * 4..][..12], &image.pixels[..12]);
+}
+
+#[test]
+fn oversized_headers() {
+    // Sizes whose pixel count overflows 32 bits are rejected without panicking.
+    assert!(read_pnm(b"P5\n4000000000 4000000000\n255\n\0\0\0").is_err());
+    assert!(read_pnm(b"P6\n2147483647 2147483647\n255\n\0\0\0").is_err());
+
+    let mut tga = vec![0u8; 18];
+    tga[2] = 2;
+    tga[12..16].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    tga[16] = 32;
+    assert_eq!(read_tga(&tga).err(), Some("truncated pixel data"));
+
+    let wide = Image::new(size2(70000, 1));
+    assert!(write_tga(&wide, &mut Vec::new()).is_err());
+}
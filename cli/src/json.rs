@@ -0,0 +1,111 @@
+//! Just enough JSON to print the reports of the command-line interface.
+
+use std::fmt;
+
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(val) => write!(f, "{}", val),
+            Json::Int(val) => write!(f, "{}", val),
+            Json::Float(val) if val.is_finite() => write!(f, "{}", val),
+            Json::Float(_) => write!(f, "null"),
+            Json::Str(val) => write_string(val, f),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (idx, (key, val)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(key, f)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(val: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in val.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl From<bool> for Json {
+    fn from(val: bool) -> Self { Json::Bool(val) }
+}
+
+impl From<i32> for Json {
+    fn from(val: i32) -> Self { Json::Int(val as i64) }
+}
+
+impl From<u16> for Json {
+    fn from(val: u16) -> Self { Json::Int(val as i64) }
+}
+
+impl From<u32> for Json {
+    fn from(val: u32) -> Self { Json::Int(val as i64) }
+}
+
+impl From<i64> for Json {
+    fn from(val: i64) -> Self { Json::Int(val) }
+}
+
+impl From<usize> for Json {
+    fn from(val: usize) -> Self { Json::Int(val as i64) }
+}
+
+impl From<f32> for Json {
+    fn from(val: f32) -> Self { Json::Float(val as f64) }
+}
+
+impl From<&str> for Json {
+    fn from(val: &str) -> Self { Json::Str(val.to_string()) }
+}
+
+impl From<String> for Json {
+    fn from(val: String) -> Self { Json::Str(val) }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(val: Option<T>) -> Self {
+        val.map(Into::into).unwrap_or(Json::Null)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(val: Vec<T>) -> Self {
+        Json::Array(val.into_iter().map(Into::into).collect())
+    }
+}
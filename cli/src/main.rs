@@ -2,12 +2,25 @@
 extern crate serde;
 
 use clap::*;
-use texture_atlas::euclid::size2;
+use texture_atlas::euclid::{size2, vec2};
 use texture_atlas::*;
 
 use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 
+mod error;
+mod image;
+mod json;
+
+use crate::error::{CliError, CliResult, ErrorKind};
+use crate::json::Json;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
 #[derive(Serialize, Deserialize)]
 enum Allocator {
     Guillotine(AllocatorList<GuillotineAllocator>),
@@ -16,7 +29,7 @@ enum Allocator {
 }
 
 impl Allocator {
-    fn allocate(&mut self, size: Size) -> Option<(Handle, Rectangle)> {
+    fn allocate(&mut self, size: Size) -> Option<Allocation> {
         match self {
             Allocator::Guillotine(ref mut alloc) => alloc.allocate(size),
             Allocator::Shelf(ref mut alloc) => alloc.allocate(size),
@@ -32,12 +45,47 @@ impl Allocator {
         }
     }
 
-    fn dump_svg(&self, file: &mut dyn Write) {
+    fn dump_svg(&self, options: &SvgOptions, allocations: &[(Handle, SvgAllocation)], file: &mut dyn Write) -> std::io::Result<()> {
         match self {
-            Allocator::Guillotine(ref alloc) => alloc.dump_svg(file),
-            Allocator::Shelf(ref alloc) => alloc.dump_svg(file),
-            Allocator::Tiled(ref alloc) => alloc.dump_svg(file),
-        }.unwrap();
+            Allocator::Guillotine(ref alloc) => alloc.dump_svg_with_options(options, allocations, file),
+            Allocator::Shelf(ref alloc) => alloc.dump_svg_with_options(options, allocations, file),
+            Allocator::Tiled(ref alloc) => alloc.dump_svg_with_options(options, allocations, file),
+        }
+    }
+
+    fn rasterize(&self, format: PixelFormat) -> (Size, Vec<u8>) {
+        match self {
+            Allocator::Guillotine(ref alloc) => alloc.rasterize(format),
+            Allocator::Shelf(ref alloc) => alloc.rasterize(format),
+            Allocator::Tiled(ref alloc) => alloc.rasterize(format),
+        }
+    }
+
+    /// Apply the operations to a new, empty allocator with the same configuration and
+    /// write the animation.
+    fn replay(&self, ops: &[replay::ReplayOp], options: &SvgOptions, frame_interval: usize, file: &mut dyn Write) -> std::io::Result<()> {
+        match self {
+            Allocator::Guillotine(ref alloc) => {
+                let mut atlas = AllocatorList::<GuillotineAllocator>::new(alloc.size(), *alloc.config());
+                replay::replay(&mut atlas, ops, options, frame_interval, file)
+            }
+            Allocator::Shelf(ref alloc) => {
+                let mut atlas = AllocatorList::<ShelfAllocator>::new(alloc.size(), *alloc.config());
+                replay::replay(&mut atlas, ops, options, frame_interval, file)
+            }
+            Allocator::Tiled(ref alloc) => {
+                let mut atlas = AllocatorList::<TiledAllocator>::new(alloc.size(), alloc.config().clone());
+                replay::replay(&mut atlas, ops, options, frame_interval, file)
+            }
+        }
+    }
+
+    fn export(&self, kind: &str, entries: &[export::ExportEntry], options: &export::ExportOptions, file: &mut dyn Write) -> std::io::Result<()> {
+        match self {
+            Allocator::Guillotine(ref alloc) => export_atlas(alloc, kind, entries, options, file),
+            Allocator::Shelf(ref alloc) => export_atlas(alloc, kind, entries, options, file),
+            Allocator::Tiled(ref alloc) => export_atlas(alloc, kind, entries, options, file),
+        }
     }
 
     fn num_textures(&self) -> usize {
@@ -47,373 +95,1005 @@ impl Allocator {
             Allocator::Tiled(ref alloc) => alloc.num_textures(),
         }
     }
+
+    fn size(&self) -> Size {
+        match self {
+            Allocator::Guillotine(ref alloc) => alloc.size(),
+            Allocator::Shelf(ref alloc) => alloc.size(),
+            Allocator::Tiled(ref alloc) => alloc.size(),
+        }
+    }
+
+    fn allocated_space(&self) -> i64 {
+        match self {
+            Allocator::Guillotine(ref alloc) => alloc.allocated_space(),
+            Allocator::Shelf(ref alloc) => alloc.allocated_space(),
+            Allocator::Tiled(ref alloc) => alloc.allocated_space(),
+        }
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self {
+            Allocator::Guillotine(_) => "guillotine",
+            Allocator::Shelf(_) => "shelf",
+            Allocator::Tiled(_) => "tiled",
+        }
+    }
+}
+
+fn export_atlas<A: AtlasAllocator>(
+    atlas: &AllocatorList<A>,
+    kind: &str,
+    entries: &[export::ExportEntry],
+    options: &export::ExportOptions,
+    file: &mut dyn Write,
+) -> std::io::Result<()> {
+    match kind {
+        "texturepacker-hash" => export::write_texture_packer_hash(atlas, entries, options, file),
+        "texturepacker-array" => export::write_texture_packer_array(atlas, entries, options, file),
+        "bmfont" => export::write_bmfont(atlas, entries, options, "atlas", file),
+        _ => export::write_csv(atlas, entries, file),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AllocationInfo {
+    rectangle: Rectangle,
+    /// The requested size, missing from sessions saved by older versions.
+    #[serde(default)]
+    size: Option<Size>,
+    #[serde(default)]
+    layer: u16,
+    /// Order of the allocation in the session.
+    age: u32,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Session {
     atlas: Allocator,
     names: std::collections::HashMap<String, Handle>,
+    #[serde(default)]
+    allocations: std::collections::HashMap<String, AllocationInfo>,
     next_id: u32,
+    #[serde(default)]
+    num_allocations: u32,
     max_allocated_textures: usize,
-    waste: i32,
+    waste: i64,
+    /// The failed allocation with the largest area.
+    #[serde(default)]
+    largest_failed_request: Option<Size>,
+    /// The most recent allocations and deallocations, up to `history_limit` of them.
+    #[serde(default)]
+    history: Vec<replay::ReplayOp>,
+    #[serde(default = "default_history_limit")]
+    history_limit: usize,
+}
+
+/// Number of operations kept in the history of a session unless `--history-limit` is set.
+const DEFAULT_HISTORY_LIMIT: usize = 10_000;
+
+fn default_history_limit() -> usize {
+    DEFAULT_HISTORY_LIMIT
+}
+
+impl Session {
+    /// Add an operation to the history, forgetting the oldest ones past the limit.
+    ///
+    /// Once operations are dropped, the replay starts from an empty atlas with the
+    /// remaining ones.
+    fn record(&mut self, op: replay::ReplayOp) {
+        self.history.push(op);
+        if self.history.len() > self.history_limit {
+            let excess = self.history.len() - self.history_limit;
+            self.history.drain(..excess);
+        }
+    }
 }
 
 fn main() {
-    let matches = App::new("Étagère command-line interface")
-        .version("0.1")
-        .author("Nicolas Silva <nical@fastmail.com>")
-        .about("Dynamic texture atlas allocator.")
-        .subcommand(
-            SubCommand::with_name("init")
-            .about("Initialize the atlas")
-            .arg(Arg::with_name("ALGORITHM")
-                .help("Packing algorithm.")
-                .value_name("ALGORITHM")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("WIDTH")
-                .help("Rectangle width.")
-                .value_name("WIDTH")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("HEIGHT")
-                .help("Rectangle height.")
-                .value_name("HEIGHT")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("LARGE_SIZE")
-                .short("l")
-                .long("large")
-                .help("Size above which a rectangle is considered large")
-                .value_name("LARGE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("SMALL_SIZE")
-                .short("s")
-                .long("small")
-                .help("Size above which a rectangle is considered large")
-                .value_name("LARGE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("ALIGN_X")
-                .long("align-x")
-                .help("Round up the width of the allocated rectangle to a multiple of the provided value.")
-                .value_name("ALIGN_X")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("ALIGN_Y")
-                .long("align-y")
-                .help("Round up the width of the allocated rectangle to a multiple of the provided value.")
-                .value_name("ALIGN_Y")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("ATLAS")
-                .short("a")
-                .long("atlas")
-                .help("Sets the output atlas file to use")
-                .value_name("FILE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("SVG_OUTPUT")
-                .long("svg")
-                .help("Dump the atlas in an SVG file")
-                .value_name("SVG_OUTPUT")
-                .takes_value(true)
-                .required(false)
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("allocate")
-            .about("Allocate a rectangle")
-            .arg(Arg::with_name("WIDTH")
-                .help("Rectangle width.")
-                .value_name("WIDTH")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("HEIGHT")
-                .help("Rectangle height.")
-                .value_name("HEIGHT")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("NAME")
-                .short("-n")
-                .long("name")
-                .help("Set a name to identify the rectangle.")
-                .value_name("NAME")
-                .takes_value(true)
-                .required(false)
-             )
-            .arg(Arg::with_name("ATLAS")
-                .short("a")
-                .long("atlas")
-                .help("Sets the output atlas file to use")
-                .value_name("FILE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("SVG_OUTPUT")
-                .long("svg")
-                .help("Dump the atlas in an SVG file")
-                .value_name("SVG_OUTPUT")
-                .takes_value(true)
-                .required(false)
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("deallocate")
-            .about("De-allocate a rectangle")
-            .arg(Arg::with_name("NAME")
-                .help("Name of the rectangle to remove.")
-                .value_name("NAME")
-                .takes_value(true)
-                .required(true)
-             )
-            .arg(Arg::with_name("ATLAS")
-                .short("a")
-                .long("atlas")
-                .help("Sets the output file to use")
-                .value_name("FILE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("SVG_OUTPUT")
-                .long("svg")
-                .help("Dump the atlas in an SVG file")
-                .value_name("SVG_OUTPUT")
-                .takes_value(true)
-                .required(false)
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("grow")
-            .about("Resize the atlas.")
-            .arg(Arg::with_name("ATLAS")
-                .short("a")
-                .long("atlas")
-                .help("Sets the output file to use")
-                .value_name("FILE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("WIDTH")
-                .help("New width")
-                .value_name("WIDTH")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("HEIGHT")
-                .help("New height")
-                .value_name("HEIGHT")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("SVG_OUTPUT")
-                .long("svg")
-                .help("Dump the atlas in an SVG file")
-                .value_name("SVG_OUTPUT")
-                .takes_value(true)
-                .required(false)
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("rearrange")
-            .about("Rearrange the allocations to reduce fragmentation.")
-            .arg(Arg::with_name("ATLAS")
-                .short("a")
-                .long("atlas")
-                .help("Sets the output file to use")
-                .value_name("FILE")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("WIDTH")
-                .short("w")
-                .long("width")
-                .help("Change the width")
-                .value_name("WIDTH")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("HEIGHT")
-                .short("h")
-                .long("height")
-                .help("Change the height")
-                .value_name("HEIGHT")
-                .takes_value(true)
-                .required(false)
-            )
-            .arg(Arg::with_name("SVG_OUTPUT")
-                .long("svg")
-                .help("Dump the atlas in an SVG file")
-                .value_name("SVG_OUTPUT")
-                .takes_value(true)
-                .required(false)
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("svg")
-            .about("Dump the atlas as SVG")
-            .arg(Arg::with_name("ATLAS")
-                .short("-a")
-                .long("atlas")
-                .help("Input texture atlas file.")
-                .value_name("ATLAS")
-                .takes_value(true)
-             )
-            .arg(Arg::with_name("SVG_OUTPUT")
-                .help("Output SVG file to use")
-                .value_name("FILE")
-                .takes_value(true)
-                .required(false)
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("list")
-            .about("List the allocations and free rectangles in the atlas")
-            .arg(Arg::with_name("ATLAS")
-                .short("-a")
-                .long("atlas")
-                .help("Input texture atlas file.")
-                .value_name("ATLAS")
-                .takes_value(true)
-             )
-        )
-        .get_matches();
-
-    if let Some(cmd) = matches.subcommand_matches("init") {
-        init(&cmd);
-    } else if let Some(cmd) = matches.subcommand_matches("allocate") {
-        allocate(&cmd);
-    } else if let Some(cmd) = matches.subcommand_matches("deallocate") {
-        deallocate(&cmd);
-    } else if let Some(_cmd) = matches.subcommand_matches("rearrange") {
-        //rearrange(&cmd);
-    } else if let Some(_cmd) = matches.subcommand_matches("grow") {
-        //grow(&cmd);
-    } else if let Some(cmd) = matches.subcommand_matches("svg") {
-        svg(&cmd);
-    } else if let Some(_cmd) = matches.subcommand_matches("list") {
-        //list(&cmd);
-    }
-}
-
-fn read_atlas(args: &ArgMatches) -> Session {
+    let matches = app().get_matches();
+
+    let format = match matches.value_of("FORMAT") {
+        Some("json") => Format::Json,
+        _ => Format::Text,
+    };
+
+    if let Err(err) = execute(&matches, format) {
+        report_error(&err, format);
+        std::process::exit(err.kind.exit_code());
+    }
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("Étagère command-line interface")
+    .version("0.1")
+    .author("Nicolas Silva <nical@fastmail.com>")
+    .about("Dynamic texture atlas allocator.")
+    .arg(Arg::with_name("FORMAT")
+        .long("format")
+        .help("Output format of allocate, deallocate, list and info, and of errors.")
+        .value_name("FORMAT")
+        .possible_values(&["text", "json"])
+        .takes_value(true)
+        .global(true)
+    )
+    .subcommand(
+        SubCommand::with_name("init")
+        .about("Initialize the atlas")
+        .arg(Arg::with_name("ALGORITHM")
+            .help("Packing algorithm: guillotine, shelf, tiled or tiled-glyphs.")
+            .value_name("ALGORITHM")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("WIDTH")
+            .help("Rectangle width.")
+            .value_name("WIDTH")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("HEIGHT")
+            .help("Rectangle height.")
+            .value_name("HEIGHT")
+            .takes_value(true)
+            .required(false)
+        )
+        .args(&allocator_option_args())
+        .arg(Arg::with_name("HISTORY_LIMIT")
+            .long("history-limit")
+            .help("Maximum number of operations kept for the replay command, zero disables it (default: 10000).")
+            .value_name("HISTORY_LIMIT")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("ATLAS")
+            .short("a")
+            .long("atlas")
+            .help("Sets the output atlas file to use")
+            .value_name("FILE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("SVG_OUTPUT")
+            .long("svg")
+            .help("Dump the atlas in an SVG file")
+            .value_name("SVG_OUTPUT")
+            .takes_value(true)
+            .required(false)
+        )
+    )
+    .subcommand(allocate_command())
+    .subcommand(deallocate_command())
+    .subcommand(
+        SubCommand::with_name("grow")
+        .about("Resize the atlas.")
+        .arg(Arg::with_name("ATLAS")
+            .short("a")
+            .long("atlas")
+            .help("Sets the output file to use")
+            .value_name("FILE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("WIDTH")
+            .help("New width")
+            .value_name("WIDTH")
+            .takes_value(true)
+            .required(true)
+        )
+        .arg(Arg::with_name("HEIGHT")
+            .help("New height")
+            .value_name("HEIGHT")
+            .takes_value(true)
+            .required(true)
+        )
+        .arg(Arg::with_name("SVG_OUTPUT")
+            .long("svg")
+            .help("Dump the atlas in an SVG file")
+            .value_name("SVG_OUTPUT")
+            .takes_value(true)
+            .required(false)
+        )
+    )
+    .subcommand(
+        SubCommand::with_name("rearrange")
+        .about("Rearrange the allocations to reduce fragmentation.")
+        .arg(Arg::with_name("ATLAS")
+            .short("a")
+            .long("atlas")
+            .help("Sets the output file to use")
+            .value_name("FILE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("WIDTH")
+            .short("w")
+            .long("width")
+            .help("Change the width")
+            .value_name("WIDTH")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("HEIGHT")
+            .short("h")
+            .long("height")
+            .help("Change the height")
+            .value_name("HEIGHT")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("SVG_OUTPUT")
+            .long("svg")
+            .help("Dump the atlas in an SVG file")
+            .value_name("SVG_OUTPUT")
+            .takes_value(true)
+            .required(false)
+        )
+    )
+    .subcommand(svg_command())
+    .subcommand(
+        SubCommand::with_name("replay")
+        .about("Replay the history of the atlas as an animated HTML page")
+        .arg(Arg::with_name("ATLAS")
+            .short("-a")
+            .long("atlas")
+            .help("Input texture atlas file.")
+            .value_name("ATLAS")
+            .takes_value(true)
+         )
+        .arg(Arg::with_name("HTML_OUTPUT")
+            .help("Output HTML file to use")
+            .value_name("FILE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("INTERVAL")
+            .long("interval")
+            .help("Number of operations between two frames.")
+            .value_name("INTERVAL")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("COLUMNS")
+            .long("columns")
+            .help("Number of textures per row.")
+            .value_name("COLUMNS")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("TEXTURE_WIDTH")
+            .long("texture-width")
+            .help("Width of each texture in the HTML file.")
+            .value_name("TEXTURE_WIDTH")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("COLORING")
+            .long("color")
+            .help("Color the allocations by size class, age or allocator kind.")
+            .value_name("COLORING")
+            .possible_values(&["default", "size", "age", "allocator"])
+            .takes_value(true)
+            .required(false)
+        )
+    )
+    .subcommand(list_command())
+    .subcommand(info_command())
+    .subcommand(
+        SubCommand::with_name("export")
+        .about("Export the allocations as a sprite-sheet or font manifest")
+        .arg(Arg::with_name("KIND")
+            .help("Manifest format.")
+            .value_name("KIND")
+            .possible_values(&["texturepacker-hash", "texturepacker-array", "csv", "bmfont"])
+            .takes_value(true)
+            .required(true)
+        )
+        .arg(Arg::with_name("OUTPUT")
+            .help("Output file. Default: atlas.json, atlas.csv or atlas.fnt depending on the format.")
+            .value_name("FILE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("IMAGE_NAME")
+            .long("image-name")
+            .help("File name of the page images, {} is replaced with the page index. Default: atlas-{}.png.")
+            .value_name("IMAGE_NAME")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("ATLAS")
+            .short("-a")
+            .long("atlas")
+            .help("Input texture atlas file.")
+            .value_name("ATLAS")
+            .takes_value(true)
+         )
+    )
+    .subcommand(
+        SubCommand::with_name("pack")
+        .about("Pack images into atlas pages and write a manifest")
+        .arg(Arg::with_name("IMAGES")
+            .help("Input images in the binary PPM, PGM or TGA formats.")
+            .value_name("IMAGES")
+            .takes_value(true)
+            .multiple(true)
+            .required(true)
+        )
+        .arg(Arg::with_name("ALGORITHM")
+            .long("algorithm")
+            .help("Packing algorithm. Default: shelf.")
+            .value_name("ALGORITHM")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("WIDTH")
+            .long("width")
+            .help("Width of the pages. Default: 1024.")
+            .value_name("WIDTH")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("HEIGHT")
+            .long("height")
+            .help("Height of the pages. Default: 1024.")
+            .value_name("HEIGHT")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .help("Prefix of the output files: PREFIX.ron for the manifest and PREFIX-N.tga for the pages.")
+            .value_name("OUTPUT")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("PAGE_FORMAT")
+            .long("page-format")
+            .help("Image format of the pages.")
+            .value_name("PAGE_FORMAT")
+            .possible_values(&["tga", "ppm"])
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("PADDING")
+            .long("padding")
+            .help("Empty pixels around each image.")
+            .value_name("PADDING")
+            .takes_value(true)
+            .required(false)
+        )
+        .args(&allocator_option_args())
+    )
+    .subcommand(
+        SubCommand::with_name("run")
+        .about("Run a script of allocate, deallocate, svg, list and info commands and write the atlas once")
+        .arg(Arg::with_name("SCRIPT")
+            .help("Script file, one command per line. Reads from stdin if omitted or \"-\".")
+            .value_name("SCRIPT")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("ATLAS")
+            .short("a")
+            .long("atlas")
+            .help("Sets the atlas file to use")
+            .value_name("FILE")
+            .takes_value(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("SVG_OUTPUT")
+            .long("svg")
+            .help("Dump the atlas in an SVG file at the end")
+            .value_name("SVG_OUTPUT")
+            .takes_value(true)
+            .required(false)
+        )
+    )
+}
+
+fn execute(matches: &ArgMatches, format: Format) -> CliResult<()> {
+    match matches.subcommand() {
+        ("init", Some(cmd)) => init(cmd),
+        ("allocate", Some(cmd)) => allocate(cmd, format),
+        ("deallocate", Some(cmd)) => deallocate(cmd, format),
+        ("rearrange", Some(_cmd)) => Ok(()), //rearrange(&cmd),
+        ("grow", Some(_cmd)) => Ok(()), //grow(&cmd),
+        ("svg", Some(cmd)) => svg(cmd),
+        ("replay", Some(cmd)) => replay(cmd),
+        ("list", Some(cmd)) => list(cmd, format),
+        ("info", Some(cmd)) => info(cmd, format),
+        ("pack", Some(cmd)) => pack(cmd, format),
+        ("export", Some(cmd)) => export(cmd),
+        ("run", Some(cmd)) => run(cmd, format),
+        _ => Ok(()),
+    }
+}
+
+/// Options of the allocators, shared by the init and pack commands.
+fn allocator_option_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("CONFIG")
+            .short("c")
+            .long("config")
+            .help("Read the algorithm, size and options from a RON file. Flags override the file.")
+            .value_name("CONFIG")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("LARGE_SIZE")
+            .short("l")
+            .long("large")
+            .help("Size above which a rectangle is considered large (guillotine).")
+            .value_name("LARGE")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("SMALL_SIZE")
+            .short("s")
+            .long("small")
+            .help("Size below which a rectangle is considered small (guillotine).")
+            .value_name("SMALL")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("ALIGN_X")
+            .long("align-x")
+            .help("Round up the width of the allocated rectangle to a multiple of the provided value (guillotine, shelf).")
+            .value_name("ALIGN_X")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("ALIGN_Y")
+            .long("align-y")
+            .help("Round up the height of the allocated rectangle to a multiple of the provided value (guillotine, shelf).")
+            .value_name("ALIGN_Y")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("VERTICAL_BINS")
+            .long("vertical-bins")
+            .help("Use vertical instead of horizontal shelves (shelf).")
+            .required(false),
+        Arg::with_name("NO_VERTICAL_BINS")
+            .long("no-vertical-bins")
+            .help("Use horizontal shelves even if the config file asks for vertical ones (shelf).")
+            .conflicts_with("VERTICAL_BINS")
+            .required(false),
+        Arg::with_name("SHELF_HEIGHTS")
+            .long("shelf-heights")
+            .help("Rounding of the height of new shelves: exact, default or a list of MAX_HEIGHT:STEP, for example 32:4,128:16 (shelf).")
+            .value_name("SHELF_HEIGHTS")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("SHELF_BINS")
+            .long("shelf-bins")
+            .help("Number of bins of new shelves: auto, count:N or width:W (shelf).")
+            .value_name("SHELF_BINS")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("TILE_SIZES")
+            .long("tile-sizes")
+            .help("Set of tile sizes (tiled).")
+            .value_name("TILE_SIZES")
+            .possible_values(&["default", "glyphs"])
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("REGION")
+            .long("region")
+            .help("Region size of a layer, for example 512x512. Repeat once per layer (tiled).")
+            .value_name("REGION")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .required(false),
+        Arg::with_name("SLOT_ORDER")
+            .long("slot-order")
+            .help("Order in which the tiles of a region are allocated (tiled).")
+            .value_name("SLOT_ORDER")
+            .possible_values(&["stack", "row-major", "morton"])
+            .takes_value(true)
+            .required(false),
+    ]
+}
+
+fn allocate_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("allocate")
+    .about("Allocate a rectangle")
+    .arg(Arg::with_name("WIDTH")
+        .help("Rectangle width.")
+        .value_name("WIDTH")
+        .takes_value(true)
+        .required(true)
+    )
+    .arg(Arg::with_name("HEIGHT")
+        .help("Rectangle height.")
+        .value_name("HEIGHT")
+        .takes_value(true)
+        .required(true)
+    )
+    .arg(Arg::with_name("NAME")
+        .short("-n")
+        .long("name")
+        .help("Set a name to identify the rectangle.")
+        .value_name("NAME")
+        .takes_value(true)
+        .required(false)
+     )
+    .arg(Arg::with_name("ATLAS")
+        .short("a")
+        .long("atlas")
+        .help("Sets the output atlas file to use")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("SVG_OUTPUT")
+        .long("svg")
+        .help("Dump the atlas in an SVG file")
+        .value_name("SVG_OUTPUT")
+        .takes_value(true)
+        .required(false)
+    )
+}
+
+fn deallocate_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("deallocate")
+    .about("De-allocate a rectangle")
+    .arg(Arg::with_name("NAME")
+        .help("Name of the rectangle to remove.")
+        .value_name("NAME")
+        .takes_value(true)
+        .required(true)
+     )
+    .arg(Arg::with_name("ATLAS")
+        .short("a")
+        .long("atlas")
+        .help("Sets the output file to use")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("SVG_OUTPUT")
+        .long("svg")
+        .help("Dump the atlas in an SVG file")
+        .value_name("SVG_OUTPUT")
+        .takes_value(true)
+        .required(false)
+    )
+}
+
+fn svg_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("svg")
+    .about("Dump the atlas as SVG")
+    .arg(Arg::with_name("ATLAS")
+        .short("-a")
+        .long("atlas")
+        .help("Input texture atlas file.")
+        .value_name("ATLAS")
+        .takes_value(true)
+     )
+    .arg(Arg::with_name("SVG_OUTPUT")
+        .help("Output SVG file to use")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("COLUMNS")
+        .long("columns")
+        .help("Number of textures per row.")
+        .value_name("COLUMNS")
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("TEXTURE_WIDTH")
+        .long("texture-width")
+        .help("Width of each texture in the SVG file.")
+        .value_name("TEXTURE_WIDTH")
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("COLORING")
+        .long("color")
+        .help("Color the allocations by size class, age or allocator kind.")
+        .value_name("COLORING")
+        .possible_values(&["default", "size", "age", "allocator"])
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("NO_LABELS")
+        .long("no-labels")
+        .help("Don't write the names of the allocations.")
+        .required(false)
+    )
+    .arg(Arg::with_name("NO_CAPTIONS")
+        .long("no-captions")
+        .help("Don't write the texture ids and occupancy.")
+        .required(false)
+    )
+    .arg(Arg::with_name("PPM_OUTPUT")
+        .long("ppm")
+        .help("Also dump the occupancy of the atlas as a PPM image.")
+        .value_name("PPM_OUTPUT")
+        .takes_value(true)
+        .required(false)
+    )
+    .arg(Arg::with_name("GRAY")
+        .long("gray")
+        .help("Write a grayscale PGM image instead of a PPM one.")
+        .requires("PPM_OUTPUT")
+        .required(false)
+    )
+}
+
+fn list_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("list")
+    .about("List the allocations in the atlas")
+    .arg(Arg::with_name("ATLAS")
+        .short("-a")
+        .long("atlas")
+        .help("Input texture atlas file.")
+        .value_name("ATLAS")
+        .takes_value(true)
+     )
+}
+
+fn info_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("info")
+    .about("Print statistics about the atlas")
+    .arg(Arg::with_name("ATLAS")
+        .short("-a")
+        .long("atlas")
+        .help("Input texture atlas file.")
+        .value_name("ATLAS")
+        .takes_value(true)
+     )
+}
+
+fn read_atlas(args: &ArgMatches) -> CliResult<Session> {
     let atlas_file_name = args.value_of("ATLAS").unwrap_or("atlas.ron");
     let file = OpenOptions::new()
         .read(true)
         .write(true)
         .open(atlas_file_name)
-        .expect("Failed to open the atlas file.");
+        .map_err(|e| CliError::new(ErrorKind::Io, format!("Failed to open {}: {}", atlas_file_name, e)))?;
 
-    ron::de::from_reader(file).expect("Failed to parse the atlas")
+    ron::de::from_reader(file)
+        .map_err(|e| CliError::new(ErrorKind::InvalidAtlas, format!("Failed to parse {}: {}", atlas_file_name, e)))
 }
 
-fn write_atlas(session: &Session, args: &ArgMatches) {
-    let serialized: String =
-        ron::ser::to_string_pretty(&session, ron::ser::PrettyConfig::default()).unwrap();
+fn write_atlas(session: &Session, args: &ArgMatches) -> CliResult<()> {
+    let serialized: String = ron::ser::to_string_pretty(&session, ron::ser::PrettyConfig::default())
+        .map_err(|e| CliError::new(ErrorKind::InvalidAtlas, e.to_string()))?;
 
     let atlas_file_name = args.value_of("ATLAS").unwrap_or("atlas.ron");
-    let mut atlas_file =
-        std::fs::File::create(atlas_file_name).expect("Failed to open the atlas file.");
+    let mut atlas_file = std::fs::File::create(atlas_file_name)
+        .map_err(|e| CliError::new(ErrorKind::Io, format!("Failed to open {}: {}", atlas_file_name, e)))?;
+
+    atlas_file.write_all(serialized.as_bytes())?;
 
-    atlas_file
-        .write_all(serialized.as_bytes())
-        .expect("Failed to write into the atlas file.");
+    Ok(())
 }
 
-fn init(args: &ArgMatches) {
-    let w = args
-        .value_of("WIDTH")
-        .expect("Missing width.")
-        .parse::<i32>()
-        .unwrap();
-    let h = args
-        .value_of("HEIGHT")
-        .expect("Missing height.")
-        .parse::<i32>()
-        .unwrap();
-
-    let kind = args
-        .value_of("ALGORITHM")
-        .expect("Missing allocator algorithm.");
-
-    let default_options = guillotiere::DEFAULT_OPTIONS;
-    let guillotiere_options = guillotiere::AllocatorOptions {
-        alignment: size2(
-            args.value_of("ALIGN_X")
-                .map(|s| s.parse::<i32>().unwrap())
-                .unwrap_or(default_options.alignment.width),
-            args.value_of("ALIGN_Y")
-                .map(|s| s.parse::<i32>().unwrap())
-                .unwrap_or(default_options.alignment.height),
-        ),
-        small_size_threshold: args
-            .value_of("SMALL")
-            .map(|s| s.parse::<i32>().unwrap())
-            .unwrap_or(default_options.small_size_threshold),
-        large_size_threshold: args
-            .value_of("LARGE")
-            .map(|s| s.parse::<i32>().unwrap())
-            .unwrap_or(default_options.large_size_threshold),
-    };
+/// Options of the `init` and `pack` commands, from the command line or from a config file.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AtlasConfig {
+    algorithm: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    alignment: Option<(i32, i32)>,
+    small_size_threshold: Option<i32>,
+    large_size_threshold: Option<i32>,
+    vertical_bins: Option<bool>,
+    shelf_heights: Option<String>,
+    shelf_bins: Option<String>,
+    tile_sizes: Option<String>,
+    regions: Option<Vec<(i32, i32)>>,
+    slot_order: Option<String>,
+}
 
-    let size = size2(w, h);
+impl AtlasConfig {
+    fn from_args(args: &ArgMatches) -> CliResult<Self> {
+        let int = |name: &str| parse_optional_arg::<i32>(args, name);
 
-    let allocator = match kind {
-        "guillotine" => Allocator::Guillotine(AllocatorList::new(size, guillotiere_options)),
-        "shelf" => Allocator::Shelf(AllocatorList::new(size, etagere::AllocatorOptions::DEFAULT)),
-        "tiled" => Allocator::Tiled(AllocatorList::new(size, (tiled::TileSizes::WrDefault, size2(512, 512)))),
-        "tiled-glyphs" => Allocator::Tiled(AllocatorList::new(size, (tiled::TileSizes::WrGlyphs, size2(128, 128)))),
-        _ => panic!("Invalid atlas allocation algorithm")
-    };
+        let alignment = match (int("ALIGN_X")?, int("ALIGN_Y")?) {
+            (None, None) => None,
+            (x, y) => Some((x.unwrap_or(1), y.unwrap_or(1))),
+        };
+
+        let regions = match args.values_of("REGION") {
+            Some(values) => Some(values.map(parse_size).collect::<std::result::Result<Vec<_>, _>>()?),
+            None => None,
+        };
+
+        Ok(AtlasConfig {
+            algorithm: args.value_of("ALGORITHM").map(|s| s.to_string()),
+            width: int("WIDTH")?,
+            height: int("HEIGHT")?,
+            alignment,
+            small_size_threshold: int("SMALL_SIZE")?,
+            large_size_threshold: int("LARGE_SIZE")?,
+            vertical_bins: if args.is_present("VERTICAL_BINS") {
+                Some(true)
+            } else if args.is_present("NO_VERTICAL_BINS") {
+                Some(false)
+            } else {
+                None
+            },
+            shelf_heights: args.value_of("SHELF_HEIGHTS").map(|s| s.to_string()),
+            shelf_bins: args.value_of("SHELF_BINS").map(|s| s.to_string()),
+            tile_sizes: args.value_of("TILE_SIZES").map(|s| s.to_string()),
+            regions,
+            slot_order: args.value_of("SLOT_ORDER").map(|s| s.to_string()),
+        })
+    }
+
+    /// Read the config file if any, and override it with the command line options.
+    fn load(args: &ArgMatches) -> CliResult<Self> {
+        let config = match args.value_of("CONFIG") {
+            Some(file_name) => AtlasConfig::from_file(file_name)?,
+            None => AtlasConfig::default(),
+        };
+
+        Ok(config.merge(AtlasConfig::from_args(args)?))
+    }
+
+    fn from_file(file_name: &str) -> CliResult<Self> {
+        let mut text = std::fs::read_to_string(file_name)
+            .map_err(|e| CliError::new(ErrorKind::Io, format!("Failed to read {}: {}", file_name, e)))?;
+        // Allow writing `width: 1024` instead of `width: Some(1024)`.
+        if !text.contains("implicit_some") {
+            text.insert_str(0, "#![enable(implicit_some)]\n");
+        }
+
+        ron::de::from_str(&text).map_err(|e| CliError::invalid_argument(format!("Failed to parse {}: {}", file_name, e)))
+    }
+
+    /// Replace the options of this config with the ones that are set in `other`.
+    fn merge(self, other: AtlasConfig) -> Self {
+        AtlasConfig {
+            algorithm: other.algorithm.or(self.algorithm),
+            width: other.width.or(self.width),
+            height: other.height.or(self.height),
+            alignment: other.alignment.or(self.alignment),
+            small_size_threshold: other.small_size_threshold.or(self.small_size_threshold),
+            large_size_threshold: other.large_size_threshold.or(self.large_size_threshold),
+            vertical_bins: other.vertical_bins.or(self.vertical_bins),
+            shelf_heights: other.shelf_heights.or(self.shelf_heights),
+            shelf_bins: other.shelf_bins.or(self.shelf_bins),
+            tile_sizes: other.tile_sizes.or(self.tile_sizes),
+            regions: other.regions.or(self.regions),
+            slot_order: other.slot_order.or(self.slot_order),
+        }
+    }
+
+    fn build_allocator(&self) -> CliResult<Allocator> {
+        let algorithm = self.algorithm.as_deref().ok_or_else(|| CliError::invalid_argument("Missing allocator algorithm."))?;
+        let w = self.width.ok_or_else(|| CliError::invalid_argument("Missing width."))?;
+        let h = self.height.ok_or_else(|| CliError::invalid_argument("Missing height."))?;
+        if w <= 0 || h <= 0 {
+            return Err(CliError::invalid_argument(format!("Invalid atlas size {}x{}.", w, h)));
+        }
+        let size = size2(w, h);
+
+        let (supported, glyphs): (&[&str], bool) = match algorithm {
+            "guillotine" => (&["alignment", "small_size_threshold", "large_size_threshold"], false),
+            "shelf" => (&["alignment", "vertical_bins", "shelf_heights", "shelf_bins"], false),
+            "tiled" => (&["tile_sizes", "regions", "slot_order"], false),
+            "tiled-glyphs" => (&["tile_sizes", "regions", "slot_order"], true),
+            _ => return Err(CliError::invalid_argument(format!("Invalid atlas allocation algorithm: {}", algorithm))),
+        };
+
+        let set_options = [
+            ("alignment", self.alignment.is_some()),
+            ("small_size_threshold", self.small_size_threshold.is_some()),
+            ("large_size_threshold", self.large_size_threshold.is_some()),
+            ("vertical_bins", self.vertical_bins.is_some()),
+            ("shelf_heights", self.shelf_heights.is_some()),
+            ("shelf_bins", self.shelf_bins.is_some()),
+            ("tile_sizes", self.tile_sizes.is_some()),
+            ("regions", self.regions.is_some()),
+            ("slot_order", self.slot_order.is_some()),
+        ];
+        for &(name, is_set) in &set_options {
+            if is_set && !supported.contains(&name) {
+                return Err(CliError::invalid_argument(format!("Option {} does not apply to the {} allocator.", name, algorithm)));
+            }
+        }
+
+        let alignment = match self.alignment {
+            Some((x, y)) if x <= 0 || y <= 0 => return Err(CliError::invalid_argument(format!("Invalid alignment {}x{}.", x, y))),
+            Some((x, y)) => Some(size2(x, y)),
+            None => None,
+        };
+
+        Ok(match algorithm {
+            "guillotine" => {
+                let default_options = guillotiere::DEFAULT_OPTIONS;
+                Allocator::Guillotine(AllocatorList::new(size, guillotiere::AllocatorOptions {
+                    alignment: alignment.unwrap_or(default_options.alignment),
+                    small_size_threshold: self.small_size_threshold.unwrap_or(default_options.small_size_threshold),
+                    large_size_threshold: self.large_size_threshold.unwrap_or(default_options.large_size_threshold),
+                }))
+            }
+            "shelf" => {
+                let default_options = etagere::AllocatorOptions::DEFAULT;
+                Allocator::Shelf(AllocatorList::new(size, etagere::AllocatorOptions {
+                    alignment: alignment.unwrap_or(default_options.alignment),
+                    vertical_bins: self.vertical_bins.unwrap_or(default_options.vertical_bins),
+                    shelf_heights: match self.shelf_heights.as_deref() {
+                        Some(text) => parse_shelf_heights(text)?,
+                        None => default_options.shelf_heights,
+                    },
+                    shelf_bins: match self.shelf_bins.as_deref() {
+                        Some(text) => parse_shelf_bins(text)?,
+                        None => default_options.shelf_bins,
+                    },
+                }))
+            }
+            _ => {
+                let (default_tile_sizes, default_region) = if glyphs {
+                    (tiled::TileSizes::WrGlyphs, (128, 128))
+                } else {
+                    (tiled::TileSizes::WrDefault, (512, 512))
+                };
+                let tile_sizes = match self.tile_sizes.as_deref() {
+                    Some("default") => tiled::TileSizes::WrDefault,
+                    Some("glyphs") => tiled::TileSizes::WrGlyphs,
+                    Some(other) => return Err(CliError::invalid_argument(format!("Invalid tile sizes: {}", other))),
+                    None => default_tile_sizes,
+                };
+                let slot_order = match self.slot_order.as_deref() {
+                    Some("stack") => tiled::SlotOrder::Stack,
+                    Some("row-major") => tiled::SlotOrder::RowMajor,
+                    Some("morton") => tiled::SlotOrder::Morton,
+                    Some(other) => return Err(CliError::invalid_argument(format!("Invalid slot order: {}", other))),
+                    None => tiled::SlotOrder::DEFAULT,
+                };
+                let regions = self.regions.clone().unwrap_or_else(|| vec![default_region]);
+                if regions.is_empty() {
+                    return Err(CliError::invalid_argument("The tiled allocator needs at least one layer."));
+                }
+
+                let mut layers = Vec::new();
+                for (w, h) in regions {
+                    if w <= 0 || h <= 0 || w > size.width || h > size.height {
+                        return Err(CliError::invalid_argument(format!("Invalid region size {}x{} for a {}x{} atlas.", w, h, size.width, size.height)));
+                    }
+                    layers.push(tiled::TiledAllocatorOptions { region_size: size2(w, h) });
+                }
+
+                Allocator::Tiled(AllocatorList::new(size, tiled::TiledAllocatorConfig {
+                    tile_sizes,
+                    layers,
+                    slot_order,
+                }))
+            }
+        })
+    }
+}
+
+fn parse_size(text: &str) -> CliResult<(i32, i32)> {
+    let error = || CliError::invalid_argument(format!("Invalid size {}, expected WIDTHxHEIGHT.", text));
+    let mut parts = text.split('x');
+    let w = parts.next().and_then(|s| s.parse::<i32>().ok()).ok_or_else(error)?;
+    let h = parts.next().and_then(|s| s.parse::<i32>().ok()).ok_or_else(error)?;
+    if parts.next().is_some() {
+        return Err(error());
+    }
+
+    Ok((w, h))
+}
+
+/// Parse `exact`, `default` or a list of `MAX_HEIGHT:STEP` entries.
+fn parse_shelf_heights(text: &str) -> CliResult<etagere::ShelfHeights> {
+    let error = || CliError::invalid_argument(format!(
+        "Invalid shelf heights {}, expected exact, default or up to {} increasing MAX_HEIGHT:STEP entries.",
+        text, etagere::HeightSteps::MAX_LEN,
+    ));
+    match text {
+        "exact" => return Ok(etagere::ShelfHeights::Exact),
+        "default" => return Ok(etagere::ShelfHeights::DEFAULT),
+        _ => {}
+    }
+
+    let mut steps = Vec::new();
+    for entry in text.split(',') {
+        let mut parts = entry.split(':');
+        let max_height = parts.next().and_then(|s| s.parse::<i32>().ok()).ok_or_else(error)?;
+        let step = parts.next().and_then(|s| s.parse::<i32>().ok()).ok_or_else(error)?;
+        if parts.next().is_some() {
+            return Err(error());
+        }
+        steps.push(etagere::HeightStep { max_height, step });
+    }
+
+    etagere::HeightSteps::new(&steps).map(etagere::ShelfHeights::Steps).ok_or_else(error)
+}
+
+/// Parse `auto`, `count:N` or `width:W`.
+fn parse_shelf_bins(text: &str) -> CliResult<etagere::ShelfBins> {
+    let error = || CliError::invalid_argument(format!("Invalid shelf bins {}, expected auto, count:N or width:W.", text));
+    let positive = |value: &str| value.parse::<i32>().ok().filter(|&value| value > 0).ok_or_else(error);
+
+    match text.split_once(':') {
+        None if text == "auto" => Ok(etagere::ShelfBins::Auto),
+        Some(("count", count)) => Ok(etagere::ShelfBins::Count(positive(count)? as u32)),
+        Some(("width", width)) => Ok(etagere::ShelfBins::Width(positive(width)?)),
+        _ => Err(error()),
+    }
+}
+
+fn init(args: &ArgMatches) -> CliResult<()> {
+    let allocator = AtlasConfig::load(args)?.build_allocator()?;
 
     let session = Session {
         atlas: allocator,
         names: std::collections::HashMap::default(),
+        allocations: std::collections::HashMap::default(),
         next_id: 0,
+        num_allocations: 0,
         max_allocated_textures: 0,
         waste: 0,
+        largest_failed_request: None,
+        history: Vec::new(),
+        history_limit: parse_optional_arg(args, "HISTORY_LIMIT")?.unwrap_or(DEFAULT_HISTORY_LIMIT),
     };
 
-    write_atlas(&session, &args);
+    write_atlas(&session, args)?;
 
     if args.is_present("SVG_OUTPUT") {
-        svg(args);
+        write_svg(&session, args)?;
     }
+
+    Ok(())
 }
 
-fn allocate(args: &ArgMatches) {
-    let mut session = read_atlas(args);
+fn allocate(args: &ArgMatches, format: Format) -> CliResult<()> {
+    let mut session = read_atlas(args)?;
 
-    let w = args
-        .value_of("WIDTH")
-        .expect("Missing width.")
-        .parse::<i32>()
-        .unwrap();
-    let h = args
-        .value_of("HEIGHT")
-        .expect("Missing height.")
-        .parse::<i32>()
-        .unwrap();
+    let result = allocate_in_session(&mut session, args, format);
 
-    let alloc = session.atlas.allocate(size2(w, h));
+    // Write the atlas even if the allocation failed, to remember the failed request.
+    write_atlas(&session, args)?;
+    result?;
 
-    if alloc.is_none() {
-        eprintln!("Allocation of size {}x{} failed.", w, h);
-        return;
+    if args.is_present("SVG_OUTPUT") {
+        write_svg(&session, args)?;
+    }
+
+    Ok(())
+}
+
+fn allocate_in_session(session: &mut Session, args: &ArgMatches, format: Format) -> CliResult<()> {
+    let w = parse_arg::<i32>(args, "WIDTH")?;
+    let h = parse_arg::<i32>(args, "HEIGHT")?;
+
+    if let Some(name) = args.value_of("NAME") {
+        if session.names.contains_key(name) {
+            return Err(CliError::invalid_argument(format!("An allocation is already named {}.", name)));
+        }
+    }
+
+    let max_size = session.atlas.size();
+    if w <= 0 || h <= 0 || w > max_size.width || h > max_size.height {
+        return Err(CliError::invalid_argument(format!(
+            "Invalid allocation size {}x{} for {}x{} textures.", w, h, max_size.width, max_size.height,
+        )));
     }
 
-    let (alloc_id, rectangle) = alloc.unwrap();
+    let area = |size: Size| size.width as i64 * size.height as i64;
+    let alloc = match session.atlas.allocate(size2(w, h)) {
+        Some(alloc) => alloc,
+        None => {
+            let largest = session.largest_failed_request.map(area).unwrap_or(0);
+            if area(size2(w, h)) > largest {
+                session.largest_failed_request = Some(size2(w, h));
+            }
+
+            return Err(CliError::new(
+                ErrorKind::AllocationFailed,
+                format!("Allocation of size {}x{} failed.", w, h),
+            ));
+        }
+    };
 
     let name = args
         .value_of("NAME")
@@ -423,39 +1103,231 @@ fn allocate(args: &ArgMatches) {
             format!("#{}", session.next_id)
         });
 
-    println!(
-        "Allocated rectangle {} of size {}x{} at origin [{}, {}]",
-        name, w, h, rectangle.min.x, rectangle.min.y
-    );
+    let rectangle = alloc.rectangle;
+    match format {
+        Format::Text => println!(
+            "Allocated rectangle {} of size {}x{} at origin [{}, {}]{}",
+            name, w, h, rectangle.min.x, rectangle.min.y, layer_suffix(alloc.layer),
+        ),
+        Format::Json => println!("{}", Json::Object(vec![
+            ("allocated", name.as_str().into()),
+            ("size", size_json(size2(w, h))),
+            ("rectangle", rectangle_json(&rectangle)),
+            ("layer", alloc.layer.into()),
+        ])),
+    }
 
-    session.names.insert(name, alloc_id);
+    session.num_allocations += 1;
+    session.allocations.insert(name.clone(), AllocationInfo {
+        rectangle,
+        size: Some(size2(w, h)),
+        layer: alloc.layer,
+        age: session.num_allocations,
+    });
+    session.record(replay::ReplayOp::Allocate {
+        name: name.clone(),
+        size: size2(w, h),
+    });
+    session.names.insert(name, alloc.handle);
     session.max_allocated_textures = session
         .max_allocated_textures
         .max(session.atlas.num_textures());
-    session.waste += rectangle.area() - w * h;
+    session.waste += area(rectangle.size()) - area(size2(w, h));
 
-    write_atlas(&session, args);
+    Ok(())
+}
+
+fn deallocate(args: &ArgMatches, format: Format) -> CliResult<()> {
+    let mut session = read_atlas(args)?;
+
+    deallocate_in_session(&mut session, args, format)?;
+
+    write_atlas(&session, args)?;
 
     if args.is_present("SVG_OUTPUT") {
-        svg(args);
+        write_svg(&session, args)?;
     }
-}
 
-fn deallocate(args: &ArgMatches) {
-    let mut session = read_atlas(args);
+    Ok(())
+}
 
-    let name = args.value_of("NAME").expect("Need a rectangle name");
-    let id = session.names.remove(name).unwrap();
+fn deallocate_in_session(session: &mut Session, args: &ArgMatches, format: Format) -> CliResult<()> {
+    let name = args.value_of("NAME").ok_or_else(|| CliError::invalid_argument("Need a rectangle name."))?;
+    let id = session.names.remove(name).ok_or_else(|| {
+        CliError::new(ErrorKind::UnknownAllocation, format!("No allocation named {}.", name))
+    })?;
+    session.allocations.remove(name);
+    session.record(replay::ReplayOp::Deallocate { name: name.to_string() });
 
     session.atlas.deallocate(id);
 
-    write_atlas(&session, args);
+    match format {
+        Format::Text => println!("Deallocated rectangle {}", name),
+        Format::Json => println!("{}", Json::Object(vec![("deallocated", name.into())])),
+    }
 
-    if args.is_present("SVG_OUTPUT") {
-        svg(args);
+    Ok(())
+}
+
+fn list(args: &ArgMatches, format: Format) -> CliResult<()> {
+    let session = read_atlas(args)?;
+
+    list_session(&session, format);
+
+    Ok(())
+}
+
+fn list_session(session: &Session, format: Format) {
+    let mut allocations: Vec<(&String, &AllocationInfo)> = session.allocations.iter().collect();
+    allocations.sort_by_key(|(_, info)| info.age);
+
+    if format == Format::Json {
+        let allocations = allocations.iter().map(|(name, info)| Json::Object(vec![
+            ("name", name.as_str().into()),
+            ("rectangle", rectangle_json(&info.rectangle)),
+            ("layer", info.layer.into()),
+        ])).collect();
+
+        println!("{}", Json::Object(vec![
+            ("allocations", Json::Array(allocations)),
+            ("textures", session.atlas.num_textures().into()),
+        ]));
+
+        return;
+    }
+
+    println!("# Allocated rectangles");
+    for (name, info) in allocations {
+        println!(
+            " - {}: size {}x{} at origin [{}, {}]{}",
+            name,
+            info.rectangle.width(),
+            info.rectangle.height(),
+            info.rectangle.min.x,
+            info.rectangle.min.y,
+            layer_suffix(info.layer),
+        );
+    }
+
+    println!("# {} texture(s)", session.atlas.num_textures());
+}
+
+fn info(args: &ArgMatches, format: Format) -> CliResult<()> {
+    let session = read_atlas(args)?;
+
+    print_info(&session, format);
+
+    Ok(())
+}
+
+fn print_info(session: &Session, format: Format) {
+    let size = session.atlas.size();
+    match format {
+        Format::Text => {
+            println!("Algorithm: {}", session.atlas.algorithm());
+            println!("Texture size: {}x{}", size.width, size.height);
+            println!("Textures: {} (at most {})", session.atlas.num_textures(), session.max_allocated_textures);
+            println!("Allocations: {}", session.names.len());
+            println!("Waste: {}", session.waste);
+            println!("Occupancy: {:.1}%", occupancy(session));
+            match session.largest_failed_request {
+                Some(size) => println!("Largest failed request: {}x{}", size.width, size.height),
+                None => println!("Largest failed request: none"),
+            }
+        }
+        Format::Json => println!("{}", info_json(session)),
+    }
+}
+
+fn info_json(session: &Session) -> Json {
+    Json::Object(vec![
+        ("algorithm", session.atlas.algorithm().into()),
+        ("texture_size", size_json(session.atlas.size())),
+        ("textures", session.atlas.num_textures().into()),
+        ("max_allocated_textures", session.max_allocated_textures.into()),
+        ("allocations", session.names.len().into()),
+        ("waste", session.waste.into()),
+        ("occupancy", occupancy(session).into()),
+        ("largest_failed_request", session.largest_failed_request.map(size_json).into()),
+    ])
+}
+
+/// The allocated space in percents of the space of all textures.
+fn occupancy(session: &Session) -> f32 {
+    let total_space = session.atlas.size().area() as i64 * session.atlas.num_textures() as i64;
+    if total_space > 0 {
+        session.atlas.allocated_space() as f32 * 100.0 / total_space as f32
+    } else {
+        0.0
     }
 }
 
+/// Run a script against a single session, one command per line.
+///
+/// Empty lines and lines starting with `#` are ignored. Failed allocations are reported
+/// and the script continues. Any other error stops the script without writing the atlas.
+fn run(args: &ArgMatches, format: Format) -> CliResult<()> {
+    let mut session = read_atlas(args)?;
+
+    let script = match args.value_of("SCRIPT") {
+        None | Some("-") => {
+            let mut script = String::new();
+            std::io::stdin().read_to_string(&mut script)?;
+            script
+        }
+        Some(file_name) => std::fs::read_to_string(file_name)?,
+    };
+
+    let app = App::new("script")
+        .setting(AppSettings::NoBinaryName)
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(allocate_command())
+        .subcommand(deallocate_command())
+        .subcommand(svg_command())
+        .subcommand(list_command())
+        .subcommand(info_command());
+
+    for (line_idx, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let at_line = |err: CliError| CliError::new(err.kind, format!("Line {}: {}", line_idx + 1, err.message));
+
+        let matches = app.clone()
+            .get_matches_from_safe(line.split_whitespace())
+            .map_err(|e| at_line(CliError::invalid_argument(e.message)))?;
+
+        let result = match matches.subcommand() {
+            ("allocate", Some(cmd)) => allocate_in_session(&mut session, cmd, format)
+                .and_then(|_| write_svg_if_requested(&session, cmd)),
+            ("deallocate", Some(cmd)) => deallocate_in_session(&mut session, cmd, format)
+                .and_then(|_| write_svg_if_requested(&session, cmd)),
+            ("svg", Some(cmd)) => write_svg(&session, cmd),
+            ("list", Some(_)) => {
+                list_session(&session, format);
+                Ok(())
+            }
+            ("info", Some(_)) => {
+                // The script's session isn't written yet, print it from memory.
+                print_info(&session, format);
+                Ok(())
+            }
+            _ => unreachable!(),
+        };
+
+        match result {
+            Err(err) if err.kind == ErrorKind::AllocationFailed => report_error(&at_line(err), format),
+            result => result.map_err(at_line)?,
+        }
+    }
+
+    write_atlas(&session, args)?;
+
+    write_svg_if_requested(&session, args)
+}
+
 /*
 fn rearrange(args: &ArgMatches) {
     let mut session = read_atlas(args);
@@ -522,48 +1394,535 @@ fn grow(args: &ArgMatches) {
     }
 }
 
-fn list(args: &ArgMatches) {
-    let session = read_atlas(args);
+*/
+
+fn svg(args: &ArgMatches) -> CliResult<()> {
+    let session = read_atlas(args)?;
 
-    println!("# Allocated rectangles");
-    session.atlas.for_each_allocated_rectangle(|id, rect| {
-        for (name, &id2) in &session.names {
-            if id2 != id {
-                continue;
+    write_svg(&session, args)
+}
+
+fn write_svg_if_requested(session: &Session, args: &ArgMatches) -> CliResult<()> {
+    if args.is_present("SVG_OUTPUT") {
+        write_svg(session, args)?;
+    }
+
+    Ok(())
+}
+
+fn write_svg(session: &Session, args: &ArgMatches) -> CliResult<()> {
+    let svg_file_name = args.value_of("SVG_OUTPUT").unwrap_or("atlas.svg");
+    let mut svg_file = File::create(svg_file_name)?;
+
+    let options = svg_options(args)?;
+
+    let mut allocations = Vec::new();
+    for (name, handle) in &session.names {
+        if let Some(info) = session.allocations.get(name) {
+            allocations.push((*handle, SvgAllocation {
+                rectangle: info.rectangle,
+                layer: info.layer,
+                label: Some(name),
+                age: info.age,
+            }));
+        }
+    }
+
+    session.atlas.dump_svg(&options, &allocations, &mut svg_file)?;
+
+    write_ppm_if_requested(session, args)
+}
+
+fn svg_options(args: &ArgMatches) -> CliResult<SvgOptions> {
+    let default_options = SvgOptions::DEFAULT;
+    Ok(SvgOptions {
+        columns: parse_optional_arg(args, "COLUMNS")?
+            .unwrap_or(default_options.columns),
+        texture_width: parse_optional_arg(args, "TEXTURE_WIDTH")?
+            .unwrap_or(default_options.texture_width),
+        coloring: match args.value_of("COLORING") {
+            Some("size") => svg::Coloring::SizeClass,
+            Some("age") => svg::Coloring::Age,
+            Some("allocator") => svg::Coloring::AllocatorKind,
+            _ => svg::Coloring::Default,
+        },
+        labels: !args.is_present("NO_LABELS"),
+        captions: !args.is_present("NO_CAPTIONS"),
+        .. default_options
+    })
+}
+
+fn replay(args: &ArgMatches) -> CliResult<()> {
+    let session = read_atlas(args)?;
+
+    let file_name = args.value_of("HTML_OUTPUT").unwrap_or("atlas.html");
+    let mut file = File::create(file_name)?;
+
+    let frame_interval = parse_optional_arg(args, "INTERVAL")?.unwrap_or(1);
+
+    session.atlas.replay(&session.history, &svg_options(args)?, frame_interval, &mut file)?;
+
+    Ok(())
+}
+
+/// Write the occupancy image next to the SVG output if `--ppm` is set.
+fn write_ppm_if_requested(session: &Session, args: &ArgMatches) -> CliResult<()> {
+    let file_name = match args.value_of("PPM_OUTPUT") {
+        Some(file_name) => file_name,
+        None => return Ok(()),
+    };
+    let mut file = File::create(file_name)?;
+
+    if args.is_present("GRAY") {
+        let (size, pixels) = session.atlas.rasterize(PixelFormat::Gray8);
+        raster::write_pgm(size, &pixels, &mut file)?;
+    } else {
+        let (size, pixels) = session.atlas.rasterize(PixelFormat::Rgb8);
+        raster::write_ppm(size, &pixels, &mut file)?;
+    }
+
+    Ok(())
+}
+
+fn export(args: &ArgMatches) -> CliResult<()> {
+    let session = read_atlas(args)?;
+
+    let kind = args.value_of("KIND").unwrap();
+    let default_name = match kind {
+        "csv" => "atlas.csv",
+        "bmfont" => "atlas.fnt",
+        _ => "atlas.json",
+    };
+    let mut file = File::create(args.value_of("OUTPUT").unwrap_or(default_name))?;
+
+    let mut allocations: Vec<(&String, &AllocationInfo)> = session.allocations.iter().collect();
+    allocations.sort_by_key(|(_, info)| info.age);
+
+    let mut entries = Vec::with_capacity(allocations.len());
+    for (name, info) in allocations {
+        if let Some(&handle) = session.names.get(name) {
+            let size = info.size.unwrap_or_else(|| info.rectangle.size());
+            entries.push(export::ExportEntry { name, handle, rectangle: info.rectangle, size, layer: info.layer });
+        }
+    }
+
+    let options = export::ExportOptions {
+        image_name: args.value_of("IMAGE_NAME").unwrap_or(export::ExportOptions::DEFAULT.image_name),
+    };
+
+    session.atlas.export(kind, &entries, &options, &mut file)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidInput => CliError::invalid_argument(e.to_string()),
+            _ => e.into(),
+        })
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackManifest {
+    page_size: (i32, i32),
+    pages: Vec<PackedPage>,
+    sprites: Vec<PackedSprite>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedPage {
+    texture: u32,
+    layer: u16,
+    file: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedSprite {
+    name: String,
+    texture: u32,
+    layer: u16,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+fn pack(args: &ArgMatches, format: Format) -> CliResult<()> {
+    let defaults = AtlasConfig {
+        algorithm: Some("shelf".to_string()),
+        width: Some(1024),
+        height: Some(1024),
+        .. AtlasConfig::default()
+    };
+    let mut atlas = defaults.merge(AtlasConfig::load(args)?).build_allocator()?;
+    let page_size = atlas.size();
+
+    let padding = parse_optional_arg::<i32>(args, "PADDING")?.unwrap_or(0);
+    if padding < 0 {
+        return Err(CliError::invalid_argument("The padding can't be negative."));
+    }
+
+    let mut images = Vec::new();
+    let mut names = std::collections::HashSet::new();
+    for file_name in args.values_of("IMAGES").unwrap() {
+        let name = std::path::Path::new(file_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file_name.to_string());
+        if !names.insert(name.clone()) {
+            return Err(CliError::invalid_argument(format!("Several images are named {}.", name)));
+        }
+
+        images.push((name, image::read_image(file_name)?));
+    }
+
+    // Tallest first, which suits shelf packing and doesn't hurt the others much.
+    images.sort_by_key(|(_, image)| (-image.size.height, -image.size.width));
+
+    let mut placements = Vec::with_capacity(images.len());
+    for (name, image) in &images {
+        let size = size2(image.size.width + padding * 2, image.size.height + padding * 2);
+        let alloc = atlas.allocate(size).ok_or_else(|| CliError::new(
+            ErrorKind::AllocationFailed,
+            format!("Failed to allocate {} ({}x{}).", name, size.width, size.height),
+        ))?;
+        placements.push(((alloc.texture().serialize(), alloc.layer), alloc.rectangle.min + vec2(padding, padding)));
+    }
+
+    // One page per layer of each texture.
+    let mut layers: Vec<(u32, u16)> = placements.iter().map(|&(page, _)| page).collect();
+    layers.sort_unstable();
+    layers.dedup();
+
+    let prefix = args.value_of("OUTPUT").unwrap_or("sprites");
+    let extension = args.value_of("PAGE_FORMAT").unwrap_or("tga");
+
+    let mut pages = Vec::new();
+    for &(texture, layer) in &layers {
+        let mut page = image::Image::new(page_size);
+        for ((_, image), &(image_page, origin)) in images.iter().zip(placements.iter()) {
+            if image_page == (texture, layer) {
+                page.blit(image, origin.x, origin.y);
             }
+        }
 
-            println!(
-                " - {}: size {}x{} at origin [{}, {}]",
-                name,
-                rect.size().width,
-                rect.size().height,
-                rect.min.x,
-                rect.min.y
-            );
+        let file_name = format!("{}-{}.{}", prefix, pages.len(), extension);
+        let mut file = File::create(&file_name)?;
+        match extension {
+            "ppm" => raster::write_ppm(page_size, &page.to_rgb(), &mut file)?,
+            _ => image::write_tga(&page, &mut file)?,
+        }
 
-            break;
+        pages.push(PackedPage { texture, layer, file: file_name });
+    }
+
+    let mut sprites: Vec<PackedSprite> = images.iter().zip(placements.iter()).map(|((name, image), &((texture, layer), origin))| {
+        PackedSprite {
+            name: name.clone(),
+            texture,
+            layer,
+            x: origin.x,
+            y: origin.y,
+            width: image.size.width,
+            height: image.size.height,
         }
-    });
+    }).collect();
+    sprites.sort_by(|a, b| a.name.cmp(&b.name));
 
-    println!("# Free rectangles");
-    session.atlas.for_each_free_rectangle(|rect| {
-        println!(
-            " - size {}x{} at origin [{}, {}]",
-            rect.size().width,
-            rect.size().height,
-            rect.min.x,
-            rect.min.y
-        );
-    });
+    let manifest = PackManifest {
+        page_size: (page_size.width, page_size.height),
+        pages,
+        sprites,
+    };
+
+    let manifest_name = format!("{}.ron", prefix);
+    let serialized = ron::ser::to_string_pretty(&manifest, ron::ser::PrettyConfig::default())
+        .map_err(|e| CliError::new(ErrorKind::Io, e.to_string()))?;
+    std::fs::write(&manifest_name, serialized)?;
+
+    match format {
+        Format::Text => println!(
+            "Packed {} images into {} page(s), manifest written to {}",
+            manifest.sprites.len(), manifest.pages.len(), manifest_name,
+        ),
+        Format::Json => println!("{}", Json::Object(vec![
+            ("images", manifest.sprites.len().into()),
+            ("pages", manifest.pages.len().into()),
+            ("manifest", manifest_name.into()),
+        ])),
+    }
+
+    Ok(())
 }
 
-*/
+fn parse_arg<T: std::str::FromStr>(args: &ArgMatches, name: &str) -> CliResult<T> {
+    parse_optional_arg(args, name)?
+        .ok_or_else(|| CliError::invalid_argument(format!("Missing {} argument.", name)))
+}
+
+fn parse_optional_arg<T: std::str::FromStr>(args: &ArgMatches, name: &str) -> CliResult<Option<T>> {
+    args.value_of(name)
+        .map(|s| s.parse::<T>().map_err(|_| CliError::invalid_argument(format!("Invalid {} value: {}", name, s))))
+        .transpose()
+}
+
+/// Mention the layer of an allocation, unless it is the first one.
+fn layer_suffix(layer: u16) -> String {
+    if layer == 0 {
+        String::new()
+    } else {
+        format!(" in layer {}", layer)
+    }
+}
 
-fn svg(args: &ArgMatches) {
-    let session = read_atlas(args);
+fn size_json(size: Size) -> Json {
+    Json::Object(vec![("width", size.width.into()), ("height", size.height.into())])
+}
 
-    let svg_file_name = args.value_of("SVG_OUTPUT").unwrap_or("atlas.svg");
-    let mut svg_file = File::create(svg_file_name).expect("Failed to open the SVG file.");
+fn rectangle_json(rectangle: &Rectangle) -> Json {
+    Json::Object(vec![
+        ("x", rectangle.min.x.into()),
+        ("y", rectangle.min.y.into()),
+        ("width", rectangle.width().into()),
+        ("height", rectangle.height().into()),
+    ])
+}
+
+fn report_error(err: &CliError, format: Format) {
+    match format {
+        Format::Text => eprintln!("error: {}", err),
+        Format::Json => eprintln!("{}", err.to_json()),
+    }
+}
+
+/// A directory of its own for the files of a test.
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("atlas-cmd-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+#[cfg(test)]
+fn run_command(args: &[&str]) -> CliResult<()> {
+    let matches = app()
+        .get_matches_from_safe(std::iter::once("atlas").chain(args.iter().copied()))
+        .map_err(|e| CliError::invalid_argument(e.message))?;
+
+    execute(&matches, Format::Json)
+}
+
+#[cfg(test)]
+fn read_session(file_name: &str) -> Session {
+    ron::de::from_reader(File::open(file_name).unwrap()).unwrap()
+}
+
+#[test]
+fn run_script() {
+    let dir = test_dir("run-script");
+    let atlas = dir.join("atlas.ron");
+    let atlas = atlas.to_str().unwrap();
+    let script = dir.join("script.txt");
+    let script = script.to_str().unwrap();
+
+    run_command(&["init", "shelf", "256", "256", "--atlas", atlas]).unwrap();
+    std::fs::write(script, "# Comments and empty lines are skipped.\n\
+        allocate 10 20 --name a\n\
+        \n\
+        allocate 30 30 --name b\n\
+        list\n\
+        deallocate a\n\
+        info\n").unwrap();
+    run_command(&["run", script, "--atlas", atlas]).unwrap();
+
+    let session = read_session(atlas);
+    assert_eq!(session.names.keys().collect::<Vec<_>>(), vec!["b"]);
+    assert_eq!(session.allocations["b"].size, Some(size2(30, 30)));
+    assert_eq!(session.history.len(), 3);
+
+    // A line that fails stops the script before the atlas is written.
+    std::fs::write(script, "allocate 10 10 --name c\ndeallocate a\n").unwrap();
+    let err = run_command(&["run", script, "--atlas", atlas]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownAllocation);
+    assert!(err.message.starts_with("Line 2: "));
+    assert!(!read_session(atlas).names.contains_key("c"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn run_script_failed_allocation() {
+    let dir = test_dir("run-failed-allocation");
+    let atlas = dir.join("atlas.ron");
+    let atlas = atlas.to_str().unwrap();
+    let script = dir.join("script.txt");
+    let script = script.to_str().unwrap();
+
+    // Tiles don't get larger than 512x512.
+    run_command(&["init", "tiled", "1024", "1024", "--atlas", atlas]).unwrap();
+    std::fs::write(script, "allocate 600 600 --name big\nallocate 10 10 --name small\n").unwrap();
+    run_command(&["run", script, "--atlas", atlas]).unwrap();
+
+    let session = read_session(atlas);
+    assert_eq!(session.names.keys().collect::<Vec<_>>(), vec!["small"]);
+    assert_eq!(session.largest_failed_request, Some(size2(600, 600)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_file() {
+    let dir = test_dir("config-file");
+    let atlas = dir.join("atlas.ron");
+    let atlas = atlas.to_str().unwrap();
+    let config = dir.join("config.ron");
+    let config = config.to_str().unwrap();
+
+    std::fs::write(config, "(algorithm: \"guillotine\", width: 256, height: 256, vertical_bins: true)").unwrap();
+    let err = run_command(&["init", "--config", config, "--atlas", atlas]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidArgument);
+    assert!(err.message.contains("vertical_bins"));
+
+    run_command(&["init", "shelf", "512", "128", "--no-vertical-bins", "--config", config, "--atlas", atlas]).unwrap();
+    match read_session(atlas).atlas {
+        Allocator::Shelf(list) => {
+            assert_eq!(list.size(), size2(512, 128));
+            assert!(!list.config().vertical_bins);
+        }
+        _ => panic!("expected a shelf allocator"),
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn shelf_and_tile_options() {
+    let dir = test_dir("shelf-and-tile-options");
+    let atlas = dir.join("atlas.ron");
+    let atlas = atlas.to_str().unwrap();
+    let config = dir.join("config.ron");
+    let config = config.to_str().unwrap();
+
+    std::fs::write(config, "(algorithm: \"shelf\", width: 256, height: 256, shelf_heights: \"exact\", shelf_bins: \"count:3\")").unwrap();
+    run_command(&["init", "--shelf-heights", "16:4,64:8", "--config", config, "--atlas", atlas]).unwrap();
+    match read_session(atlas).atlas {
+        Allocator::Shelf(list) => {
+            let steps = [etagere::HeightStep { max_height: 16, step: 4 }, etagere::HeightStep { max_height: 64, step: 8 }];
+            assert_eq!(list.config().shelf_heights, etagere::ShelfHeights::Steps(etagere::HeightSteps::new(&steps).unwrap()));
+            assert_eq!(list.config().shelf_bins, etagere::ShelfBins::Count(3));
+        }
+        _ => panic!("expected a shelf allocator"),
+    }
+
+    for invalid in &["64:8,16:4", "16:0", "16", "big"] {
+        assert!(run_command(&["init", "shelf", "256", "256", "--shelf-heights", invalid, "--atlas", atlas]).is_err());
+    }
+    for invalid in &["count:0", "width:-4", "4", "auto:4"] {
+        assert!(run_command(&["init", "shelf", "256", "256", "--shelf-bins", invalid, "--atlas", atlas]).is_err());
+    }
+
+    run_command(&["init", "tiled", "512", "512", "--slot-order", "morton", "--atlas", atlas]).unwrap();
+    match read_session(atlas).atlas {
+        Allocator::Tiled(list) => assert_eq!(list.config().slot_order, tiled::SlotOrder::Morton),
+        _ => panic!("expected a tiled allocator"),
+    }
+
+    // Options of the other allocators are rejected.
+    let err = run_command(&["init", "shelf", "256", "256", "--slot-order", "morton", "--atlas", atlas]).unwrap_err();
+    assert!(err.message.contains("slot_order"));
+    let err = run_command(&["init", "tiled", "512", "512", "--shelf-bins", "auto", "--atlas", atlas]).unwrap_err();
+    assert!(err.message.contains("shelf_bins"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unknown_options() {
+    let dir = test_dir("unknown-options");
+    let atlas = dir.join("atlas.ron");
+    let atlas = atlas.to_str().unwrap();
+    let config = dir.join("config.ron");
+    let config = config.to_str().unwrap();
+    let script = dir.join("script.txt");
+    let script = script.to_str().unwrap();
+
+    std::fs::write(config, "(algorithm: \"shelf\", width: 256, height: 256, shelf_size: 8)").unwrap();
+    let err = run_command(&["init", "--config", config, "--atlas", atlas]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidArgument);
+
+    let err = run_command(&["init", "shelf", "256", "256", "--shelf-size", "8", "--atlas", atlas]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidArgument);
+
+    run_command(&["init", "shelf", "256", "256", "--atlas", atlas]).unwrap();
+    std::fs::write(script, "allocate 10 10 --color red\n").unwrap();
+    let err = run_command(&["run", script, "--atlas", atlas]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidArgument);
+    assert!(err.message.starts_with("Line 1: "));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn info_fields() {
+    let dir = test_dir("info-fields");
+    let atlas = dir.join("atlas.ron");
+    let atlas = atlas.to_str().unwrap();
+
+    run_command(&["init", "shelf", "256", "256", "--atlas", atlas]).unwrap();
+    run_command(&["allocate", "100", "100", "--name", "a", "--atlas", atlas]).unwrap();
+    run_command(&["info", "--atlas", atlas]).unwrap();
+
+    let info = info_json(&read_session(atlas)).to_string();
+    let fields = [
+        "\"algorithm\":\"shelf\"",
+        "\"texture_size\":{\"width\":256,\"height\":256}",
+        "\"textures\":1",
+        "\"max_allocated_textures\":1",
+        "\"allocations\":1",
+        "\"waste\":",
+        "\"occupancy\":",
+        "\"largest_failed_request\":null",
+    ];
+    for field in &fields {
+        assert!(info.contains(field), "{} in {}", field, info);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn pack_images() {
+    let dir = test_dir("pack-images");
+    let mut inputs = Vec::new();
+    for (name, size, color) in [("a", size2(3, 2), [255, 0, 0]), ("b", size2(4, 5), [0, 0, 255])] {
+        let file_name = dir.join(format!("{}.ppm", name)).to_str().unwrap().to_string();
+        let pixels: Vec<u8> = (0..size.area()).flat_map(|_| color).collect();
+        raster::write_ppm(size, &pixels, &mut File::create(&file_name).unwrap()).unwrap();
+        inputs.push(file_name);
+    }
+    let prefix = dir.join("sprites");
+    let prefix = prefix.to_str().unwrap();
+
+    run_command(&[
+        "pack", &inputs[0], &inputs[1],
+        "--width", "32", "--height", "32", "--padding", "1", "--page-format", "ppm", "-o", prefix,
+    ]).unwrap();
+
+    let manifest: PackManifest = ron::de::from_str(&std::fs::read_to_string(format!("{}.ron", prefix)).unwrap()).unwrap();
+    assert_eq!(manifest.page_size, (32, 32));
+    assert_eq!(manifest.pages.len(), 1);
+    assert_eq!(manifest.pages[0].file, format!("{}-0.ppm", prefix));
+    let names: Vec<&str> = manifest.sprites.iter().map(|sprite| sprite.name.as_str()).collect();
+    assert_eq!(names, vec!["a", "b"]);
+
+    // The pixels of each sprite are in the page at the position of the manifest.
+    let page = image::read_image(&manifest.pages[0].file).unwrap();
+    for (sprite, color) in manifest.sprites.iter().zip(&[[255, 0, 0], [0, 0, 255]]) {
+        assert_eq!((sprite.texture, sprite.layer), (manifest.pages[0].texture, manifest.pages[0].layer));
+        assert!(sprite.x >= 1 && sprite.y >= 1);
+        for (x, y) in [(sprite.x, sprite.y), (sprite.x + sprite.width - 1, sprite.y + sprite.height - 1)] {
+            let idx = ((y * 32 + x) * 4) as usize;
+            assert_eq!(&page.pixels[idx..idx + 3], color);
+        }
+    }
+    assert_eq!((manifest.sprites[0].width, manifest.sprites[0].height), (3, 2));
+    assert_eq!((manifest.sprites[1].width, manifest.sprites[1].height), (4, 5));
 
-    session.atlas.dump_svg(&mut svg_file);
+    std::fs::remove_dir_all(&dir).unwrap();
 }
@@ -0,0 +1,84 @@
+//! Packing a set of rectangles that is known up front.
+
+use crate::{AllocError, Rectangle, Size};
+
+/// Allocate a batch with each of a few orderings and in the order of `sizes`, and keep
+/// the state and the results of the ordering that had the best result.
+///
+/// `snapshot` copies the state of the target and `restore` puts a copy back. The target
+/// is restored to its initial state before each ordering and to the state left by the
+/// best ordering at the end, so the kept results are those of an actual run.
+///
+/// Results are ranked by number of failures, then number of textures and layers, then
+/// the sum of the areas of the bounding boxes of the allocations in each of them.
+pub(crate) fn allocate_batch<T, S, R: Copy>(
+    target: &mut T,
+    sizes: &[Size],
+    allocate: fn(&mut T, Size) -> Result<R, AllocError>,
+    snapshot: impl Fn(&T) -> S,
+    restore: impl Fn(&mut T, &S),
+    placement: fn(&R) -> ((u32, u16), Rectangle),
+) -> Vec<Result<R, AllocError>> {
+    let mut orderings: Vec<Vec<usize>> = Vec::new();
+    // Decreasing height, area and perimeter.
+    for ordering in 0..3 {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&idx| {
+            let s = sizes[idx];
+            std::cmp::Reverse(match ordering {
+                0 => (s.height, s.width),
+                1 => (s.area(), s.width.max(s.height)),
+                _ => (s.width + s.height, s.height),
+            })
+        });
+        if !orderings.contains(&order) {
+            orderings.push(order);
+        }
+    }
+    // The order of the request, which some allocators depend on more than on sorting.
+    let order: Vec<usize> = (0..sizes.len()).collect();
+    if !orderings.contains(&order) {
+        orderings.push(order);
+    }
+
+    let initial = snapshot(target);
+    let mut best = None;
+    for (idx, order) in orderings.iter().enumerate() {
+        if idx > 0 {
+            restore(target, &initial);
+        }
+
+        let mut results = vec![Err(AllocError::OutOfSpace); sizes.len()];
+        for &idx in order {
+            results[idx] = allocate(target, sizes[idx]);
+        }
+
+        let failures = results.iter().filter(|result| result.is_err()).count();
+        let mut bounds: Vec<((u32, u16), Rectangle)> = Vec::new();
+        for result in results.iter().filter_map(|result| result.as_ref().ok()) {
+            let (key, rect) = placement(result);
+            match bounds.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, bounds)) => *bounds = bounds.union(&rect),
+                None => bounds.push((key, rect)),
+            }
+        }
+        let area: i64 = bounds.iter().map(|(_, rect)| rect.area() as i64).sum();
+        let score = (failures, bounds.len(), area);
+
+        let better = match &best {
+            Some((best_score, _, _)) => score < *best_score,
+            None => true,
+        };
+        if better {
+            best = Some((score, snapshot(target), results));
+        }
+    }
+
+    match best {
+        Some((_, state, results)) => {
+            restore(target, &state);
+            results
+        }
+        None => Vec::new(),
+    }
+}
@@ -0,0 +1,355 @@
+//! Route allocation requests to several texture lists depending on their size.
+//!
+//! A typical setup packs small glyphs on shelves, medium images in a guillotine atlas
+//! and large images in textures of their own. Each kind of request is served by an
+//! `AllocatorList` with its own allocator type, and the dispatcher hides them behind a
+//! single handle type.
+
+use crate::{AllocatorList, AtlasAllocator, Allocation, Handle, Rectangle, Size, TextureId, point2};
+use crate::svg::{self, SvgAllocation, SvgOptions};
+
+/// The operations of `AllocatorList` that don't depend on the allocator type.
+///
+/// This lets lists with different allocator types be stored side by side as trait
+/// objects. The trait is sealed, it is only implemented by `AllocatorList`.
+pub trait DynAllocatorList: sealed::Sealed {
+    fn allocate(&mut self, size: Size) -> Option<Allocation>;
+
+    fn deallocate(&mut self, handle: Handle);
+
+    /// See `AllocatorList::end_frame`.
+    fn end_frame(&mut self);
+
+    fn is_empty(&self) -> bool;
+
+    fn num_textures(&self) -> usize;
+
+    fn allocated_space(&self) -> i64;
+
+    /// The maximum size of the textures.
+    fn size(&self) -> Size;
+
+    /// Draw the texture at the provided index in the top-left corner of `cell`, followed
+    /// by its caption below the cell.
+    fn dump_texture_into_svg(
+        &self,
+        index: usize,
+        cell: &euclid::default::Box2D<f32>,
+        caption_prefix: &str,
+        options: &SvgOptions,
+        allocations: &[(Handle, SvgAllocation)],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()>;
+}
+
+impl<Allocator: AtlasAllocator> DynAllocatorList for AllocatorList<Allocator> {
+    fn allocate(&mut self, size: Size) -> Option<Allocation> {
+        AllocatorList::allocate(self, size)
+    }
+
+    fn deallocate(&mut self, handle: Handle) {
+        AllocatorList::deallocate(self, handle)
+    }
+
+    fn end_frame(&mut self) {
+        AllocatorList::end_frame(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        AllocatorList::is_empty(self)
+    }
+
+    fn num_textures(&self) -> usize {
+        AllocatorList::num_textures(self)
+    }
+
+    fn allocated_space(&self) -> i64 {
+        AllocatorList::allocated_space(self)
+    }
+
+    fn size(&self) -> Size {
+        AllocatorList::size(self)
+    }
+
+    fn dump_texture_into_svg(
+        &self,
+        index: usize,
+        cell: &euclid::default::Box2D<f32>,
+        caption_prefix: &str,
+        options: &SvgOptions,
+        allocations: &[(Handle, SvgAllocation)],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        AllocatorList::dump_texture_into_svg(self, index, cell, caption_prefix, options, allocations, output)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl<Allocator: crate::AtlasAllocator> Sealed for crate::AllocatorList<Allocator> {}
+}
+
+/// Identifies an allocation made by an `AllocatorDispatcher`.
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DispatchHandle {
+    list: u32,
+    handle: Handle,
+}
+
+impl DispatchHandle {
+    /// The index of the list that made the allocation.
+    pub fn list(&self) -> usize {
+        self.list as usize
+    }
+
+    /// The handle of the allocation in its list.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// The texture that contains the allocation, within its list.
+    pub fn texture(&self) -> TextureId {
+        self.handle.texture()
+    }
+}
+
+/// An allocated rectangle in one of the lists of an `AllocatorDispatcher`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DispatchAllocation {
+    pub handle: DispatchHandle,
+    pub layer: u16,
+    pub rectangle: Rectangle,
+    /// The size of the texture at the time of the allocation.
+    pub texture_size: Size,
+}
+
+impl DispatchAllocation {
+    /// The index of the list that made the allocation.
+    pub fn list(&self) -> usize {
+        self.handle.list()
+    }
+
+    /// The texture that contains the allocation, within its list.
+    pub fn texture(&self) -> TextureId {
+        self.handle.texture()
+    }
+}
+
+struct DispatchedList {
+    name: String,
+    max_size: Size,
+    list: Box<dyn DynAllocatorList>,
+}
+
+/// Owns several allocator lists and sends each request to one of them depending on
+/// its size.
+///
+/// Texture ids are only unique within a list, textures are identified by the index of
+/// their list and their id.
+pub struct AllocatorDispatcher {
+    lists: Vec<DispatchedList>,
+}
+
+impl AllocatorDispatcher {
+    pub fn new() -> Self {
+        AllocatorDispatcher {
+            lists: Vec::new(),
+        }
+    }
+
+    /// Add a list that serves the requests that fit in `max_size` and weren't
+    /// claimed by a previously added list. Returns the index of the list.
+    ///
+    /// Lists should therefore be added from the smallest threshold to the largest one.
+    /// The name is used in the captions of the SVG dump.
+    pub fn add_list(&mut self, name: &str, max_size: Size, list: Box<dyn DynAllocatorList>) -> usize {
+        self.lists.push(DispatchedList {
+            name: name.to_string(),
+            max_size,
+            list,
+        });
+
+        self.lists.len() - 1
+    }
+
+    /// The index of the list that serves requests of the provided size, if any.
+    pub fn route(&self, size: Size) -> Option<usize> {
+        self.lists.iter().position(|entry| {
+            size.width <= entry.max_size.width && size.height <= entry.max_size.height
+        })
+    }
+
+    /// Allocate a rectangle in the list that serves its size.
+    ///
+    /// If that list can't allocate it, the request fails without trying the other lists.
+    pub fn allocate(&mut self, size: Size) -> Option<DispatchAllocation> {
+        let list = self.route(size)?;
+        let alloc = self.lists[list].list.allocate(size)?;
+
+        Some(DispatchAllocation {
+            handle: DispatchHandle { list: list as u32, handle: alloc.handle },
+            layer: alloc.layer,
+            rectangle: alloc.rectangle,
+            texture_size: alloc.texture_size,
+        })
+    }
+
+    pub fn deallocate(&mut self, handle: DispatchHandle) {
+        self.lists[handle.list()].list.deallocate(handle.handle);
+    }
+
+    /// Call `end_frame` on each list.
+    pub fn end_frame(&mut self) {
+        for entry in &mut self.lists {
+            entry.list.end_frame();
+        }
+    }
+
+    pub fn num_lists(&self) -> usize {
+        self.lists.len()
+    }
+
+    pub fn list(&self, index: usize) -> &dyn DynAllocatorList {
+        &*self.lists[index].list
+    }
+
+    pub fn list_mut(&mut self, index: usize) -> &mut dyn DynAllocatorList {
+        &mut *self.lists[index].list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.iter().all(|entry| entry.list.is_empty())
+    }
+
+    pub fn allocated_space(&self) -> i64 {
+        self.lists.iter().map(|entry| entry.list.allocated_space()).sum()
+    }
+
+    pub fn dump_svg(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.dump_svg_with_options(&SvgOptions::DEFAULT, &[], output)
+    }
+
+    /// Dump the textures of all lists in a single grid, list after list, with the
+    /// provided allocations drawn on top of them.
+    ///
+    /// All cells have the same width, so textures of different lists are not drawn
+    /// at the same scale.
+    pub fn dump_svg_with_options(
+        &self,
+        options: &SvgOptions,
+        allocations: &[(DispatchHandle, SvgAllocation)],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        use svg_fmt::*;
+
+        let spacing = options.spacing;
+        let cell_w = options.texture_width;
+        let cell_h = self.lists.iter()
+            .map(|entry| cell_w * entry.list.size().height as f32 / entry.list.size().width as f32)
+            .fold(0.0, f32::max);
+        let caption_h = if options.captions { svg::CAPTION_HEIGHT } else { 0.0 };
+
+        let num_textures = self.lists.iter().map(|entry| entry.list.num_textures()).sum();
+        let (columns, rows) = svg::grid_size(num_textures, options);
+
+        let svg_w = spacing + (cell_w + spacing) * columns as f32;
+        let svg_h = spacing + (cell_h + caption_h + spacing) * rows as f32;
+
+        writeln!(output, "{}", BeginSvg { w: svg_w, h: svg_h } )?;
+
+        // Background.
+        writeln!(output,
+            "    {}",
+            rectangle(0.0, 0.0, svg_w, svg_h)
+                .inflate(1.0, 1.0)
+                .fill(rgb(30, 30, 30))
+        )?;
+
+        let mut cell = 0;
+        let mut list_allocations = Vec::new();
+        for (list_idx, entry) in self.lists.iter().enumerate() {
+            list_allocations.clear();
+            list_allocations.extend(
+                allocations.iter()
+                    .filter(|(handle, _)| handle.list() == list_idx)
+                    .map(|(handle, alloc)| (handle.handle, *alloc))
+            );
+
+            let caption_prefix = format!("{}: ", entry.name);
+            for idx in 0..entry.list.num_textures() {
+                let x = spacing + (cell % columns) as f32 * (cell_w + spacing);
+                let y = spacing + (cell / columns) as f32 * (cell_h + caption_h + spacing);
+                let cell_rect = euclid::default::Box2D::new(point2(x, y), point2(x + cell_w, y + cell_h));
+                entry.list.dump_texture_into_svg(idx, &cell_rect, &caption_prefix, options, &list_allocations, output)?;
+                cell += 1;
+            }
+        }
+
+        writeln!(output, "{}", EndSvg)
+    }
+}
+
+impl Default for AllocatorDispatcher {
+    fn default() -> Self {
+        AllocatorDispatcher::new()
+    }
+}
+
+#[test]
+fn dispatch_by_size() {
+    use crate::{GuillotineAllocator, ShelfAllocator, size2};
+
+    let mut dispatcher = AllocatorDispatcher::new();
+    let glyphs = dispatcher.add_list(
+        "glyphs",
+        size2(32, 32),
+        Box::new(AllocatorList::<ShelfAllocator>::new(size2(256, 256), crate::etagere::AllocatorOptions::DEFAULT)),
+    );
+    let images = dispatcher.add_list(
+        "images",
+        size2(512, 512),
+        Box::new(AllocatorList::<GuillotineAllocator>::new(size2(1024, 512), guillotiere::DEFAULT_OPTIONS)),
+    );
+
+    assert_eq!(dispatcher.route(size2(10, 32)), Some(glyphs));
+    assert_eq!(dispatcher.route(size2(33, 10)), Some(images));
+    assert_eq!(dispatcher.route(size2(600, 10)), None);
+    assert!(dispatcher.allocate(size2(600, 10)).is_none());
+
+    let a = dispatcher.allocate(size2(16, 16)).unwrap();
+    let b = dispatcher.allocate(size2(100, 200)).unwrap();
+    let c = dispatcher.allocate(size2(20, 30)).unwrap();
+    assert_eq!((a.list(), b.list(), c.list()), (glyphs, images, glyphs));
+    assert_eq!(dispatcher.list(glyphs).num_textures(), 1);
+    assert_eq!(dispatcher.list(images).num_textures(), 1);
+    assert_eq!(dispatcher.allocated_space(), dispatcher.list(glyphs).allocated_space() + 100 * 200);
+
+    let mut svg = Vec::new();
+    let labels = [(a.handle, "a"), (b.handle, "b"), (c.handle, "c")];
+    let allocations: Vec<(DispatchHandle, SvgAllocation)> = [a, b, c].iter().zip(labels.iter())
+        .map(|(alloc, &(handle, label))| (handle, SvgAllocation { rectangle: alloc.rectangle, layer: alloc.layer, label: Some(label), age: 0 }))
+        .collect();
+    dispatcher.dump_svg_with_options(&SvgOptions::DEFAULT, &allocations, &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.contains("glyphs: Texture 1"));
+    assert!(svg.contains("images: Texture 1"));
+
+    // Both captions are on the same row, below the tallest cell.
+    let caption_y = |caption: &str| {
+        let line = svg.lines().find(|line| line.contains(caption)).unwrap();
+        let start = line.find(" y=\"").unwrap() + 4;
+        line[start..].split('"').next().unwrap().to_string()
+    };
+    assert_eq!(caption_y("glyphs: Texture 1"), caption_y("images: Texture 1"));
+
+    dispatcher.deallocate(a.handle);
+    dispatcher.deallocate(b.handle);
+    assert!(!dispatcher.is_empty());
+    assert_eq!(dispatcher.list(images).num_textures(), 0);
+    dispatcher.deallocate(c.handle);
+    assert!(dispatcher.is_empty());
+}
@@ -1,8 +1,8 @@
-use std::num::Wrapping;
-use std::u16;
-
 pub use euclid::{point2, size2};
 
+use crate::free_list::{FreeList, FreeListHandle};
+use crate::svg::{self, SvgAllocation, SvgOptions};
+
 pub type Point = euclid::default::Point2D<i32>;
 pub type Size = euclid::default::Size2D<i32>;
 pub type Rectangle = euclid::default::Box2D<i32>;
@@ -23,56 +23,225 @@ impl AllocId {
     }
 }
 
-const BIN_BITS: u32 = 12;
-const ITEM_BITS: u32 = 12;
-const GEN_BITS: u32 = 8;
+/// ID referring to an allocated rectangle of a `WideAtlasAllocator`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct WideAllocId(pub(crate) u64);
+
+impl WideAllocId {
+    pub fn serialize(&self) -> u64 {
+        self.0
+    }
+
+    pub fn deserialize(bytes: u64) -> Self {
+        WideAllocId(bytes)
+    }
+}
+
+/// How the bin, item and generation of an allocation are packed into its id, which
+/// bounds the size of the atlas and the number of bins and items.
+///
+/// Implemented by `Compact` and `Wide`.
+pub trait IdLayout: layout::Layout {}
+
+/// 32-bit `AllocId`s with 12 bin bits, 12 item bits and 8 generation bits.
+///
+/// Up to 4095 bins and 4095 allocations per bin lifetime, and the width and height of
+/// the atlas must be smaller than 65535.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Compact;
+
+/// 64-bit `WideAllocId`s with 24 bin bits, 24 item bits and 16 generation bits.
+///
+/// Meant for very large atlases and long-running sessions with a lot of churn.
+/// Coordinates are only limited by the positive range of `i32`, at the cost of twice
+/// as much memory per shelf and bin.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Wide;
+
+impl IdLayout for Compact {}
+impl IdLayout for Wide {}
+
+mod layout {
+    use std::fmt::Debug;
+    use std::ops::{Add, AddAssign, Div, Rem, Sub, SubAssign};
+
+    /// The integer type in which shelves and bins store their coordinates and counters.
+    pub trait Coord:
+        Copy + Ord + Debug
+        + Add<Output = Self> + Sub<Output = Self> + Div<Output = Self> + Rem<Output = Self>
+        + AddAssign + SubAssign
+    {
+        const ZERO: Self;
+        const ONE: Self;
+        const MAX: Self;
+
+        /// Callers make sure that the value fits.
+        fn from_u32(value: u32) -> Self;
+
+        fn to_u32(self) -> u32;
+
+        fn wrapping_inc(self) -> Self;
+    }
+
+    macro_rules! impl_coord {
+        ($ty:ty) => {
+            impl Coord for $ty {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                const MAX: Self = <$ty>::MAX;
+
+                fn from_u32(value: u32) -> Self {
+                    value as $ty
+                }
+
+                fn to_u32(self) -> u32 {
+                    self as u32
+                }
+
+                fn wrapping_inc(self) -> Self {
+                    self.wrapping_add(1)
+                }
+            }
+        };
+    }
+
+    impl_coord!(u16);
+    impl_coord!(u32);
+
+    pub trait Layout {
+        type Id: Copy + Eq + std::hash::Hash;
+        type Coord: Coord;
+
+        const BIN_BITS: u32;
+        const ITEM_BITS: u32;
+        const GEN_BITS: u32;
+
+        /// The largest width or height of the atlas.
+        const MAX_SIDE: i32;
+
+        fn id(bits: u64) -> Self::Id;
+
+        fn bits(id: Self::Id) -> u64;
+    }
+
+    impl Layout for super::Compact {
+        type Id = super::AllocId;
+        type Coord = u16;
+
+        const BIN_BITS: u32 = 12;
+        const ITEM_BITS: u32 = 12;
+        const GEN_BITS: u32 = 8;
+
+        const MAX_SIDE: i32 = u16::MAX as i32 - 1;
+
+        fn id(bits: u64) -> super::AllocId {
+            super::AllocId(bits as u32)
+        }
+
+        fn bits(id: super::AllocId) -> u64 {
+            id.0 as u64
+        }
+    }
+
+    impl Layout for super::Wide {
+        type Id = super::WideAllocId;
+        type Coord = u32;
+
+        const BIN_BITS: u32 = 24;
+        const ITEM_BITS: u32 = 24;
+        const GEN_BITS: u32 = 16;
+
+        const MAX_SIDE: i32 = i32::MAX;
+
+        fn id(bits: u64) -> super::WideAllocId {
+            super::WideAllocId(bits)
+        }
+
+        fn bits(id: super::WideAllocId) -> u64 {
+            id.0
+        }
+    }
+}
+
+use layout::Coord;
 
-const BIN_MASK: u32   = (1 << BIN_BITS) - 1;
-const ITEM_MASK: u32  = ((1 << ITEM_BITS) - 1) << BIN_BITS;
-const GEN_MASK: u32   = ((1 << GEN_BITS) - 1) << (BIN_BITS + ITEM_BITS);
+fn max_bin_count<L: IdLayout>() -> usize {
+    (1 << L::BIN_BITS) - 1
+}
 
-const MAX_ITEMS_PER_BIN: u16 = (ITEM_MASK >> 12) as u16;
-const MAX_BIN_COUNT: usize = BIN_MASK as usize;
-const MAX_SHELF_COUNT: usize = u16::MAX as usize;
+fn max_items_per_bin<L: IdLayout>() -> u32 {
+    (1 << L::ITEM_BITS) - 1
+}
+
+fn generation_mask<L: IdLayout>() -> u32 {
+    ((1u64 << L::GEN_BITS) - 1) as u32
+}
+
+fn pack_id<L: IdLayout>(bin_index: BinIndex<L::Coord>, item: L::Coord, generation: L::Coord) -> L::Id {
+    let bin_mask = (1u64 << L::BIN_BITS) - 1;
+    let item_mask = (1u64 << L::ITEM_BITS) - 1;
+
+    L::id(
+        (bin_index.0.to_u32() as u64) & bin_mask
+        | ((item.to_u32() as u64) & item_mask) << L::BIN_BITS
+        | ((generation.to_u32() & generation_mask::<L>()) as u64) << (L::BIN_BITS + L::ITEM_BITS)
+    )
+}
+
+/// The bin index and the generation of an id.
+fn unpack_id<L: IdLayout>(id: L::Id) -> (usize, u32) {
+    let bits = L::bits(id);
+    let bin_mask = (1u64 << L::BIN_BITS) - 1;
+
+    (
+        (bits & bin_mask) as usize,
+        (bits >> (L::BIN_BITS + L::ITEM_BITS)) as u32 & generation_mask::<L>(),
+    )
+}
 
 pub type ShelfIndex = u32;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
-struct BinIndex(u16);
+struct BinIndex<C>(C);
 
-impl BinIndex {
+impl<C: Coord> BinIndex<C> {
     fn to_usize(self) -> usize {
-        self.0 as usize
+        self.0.to_u32() as usize
     }
 
-    const INVALID: Self = BinIndex(u16::MAX);
+    const INVALID: Self = BinIndex(C::MAX);
 }
 
 #[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
-struct Shelf {
-    y: u16,
-    height: u16,
-    bin_width: u16,
+#[derive(Clone)]
+struct Shelf<C> {
+    y: C,
+    height: C,
+    bin_width: C,
 
-    first_bin: BinIndex,
+    first_bin: BinIndex<C>,
 }
 
 #[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
-struct Bin {
-    x: u16,
-    free_space: u16,
+#[derive(Clone)]
+struct Bin<C> {
+    x: C,
+    free_space: C,
 
-    next: BinIndex,
+    next: BinIndex<C>,
 
     /// Bins are cleared when their reference count goes back to zero.
-    refcount: u16,
+    refcount: C,
     /// Similar to refcount except that the counter is not decremented
     /// when an item is deallocated. We only use this so that allocation
     /// ids are unique within a bin.
-    item_count: u16,
-    shelf: u16,
-    generation: Wrapping<u8>,
+    item_count: C,
+    shelf: C,
+    /// Wraps around, only the low bits that fit in the id layout are used.
+    generation: C,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -86,15 +255,159 @@ pub struct AllocatorOptions {
     ///
     /// Default value: false.
     pub vertical_bins: bool,
+    /// How the height of new shelves is rounded up.
+    ///
+    /// Default value: `ShelfHeights::DEFAULT`.
+    #[cfg_attr(feature = "serialization", serde(default))]
+    pub shelf_heights: ShelfHeights,
+    /// How new shelves are split into bins.
+    ///
+    /// Default value: `ShelfBins::Auto`.
+    #[cfg_attr(feature = "serialization", serde(default))]
+    pub shelf_bins: ShelfBins,
 }
 
 impl AllocatorOptions {
     pub const DEFAULT: Self = AllocatorOptions {
         vertical_bins: false,
         alignment: size2(1, 1),
+        shelf_heights: ShelfHeights::DEFAULT,
+        shelf_bins: ShelfBins::Auto,
     };
 }
 
+/// Rounding up the height of new shelves lets items of slightly different heights
+/// share them, at the cost of some wasted space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum ShelfHeights {
+    /// Shelves have the height of the item that creates them.
+    Exact,
+    /// The height is rounded up to a multiple of the step of the first entry with a large
+    /// enough `max_height`, or of the last entry if none is.
+    Steps(HeightSteps),
+}
+
+/// See `ShelfHeights::Steps`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct HeightStep {
+    pub max_height: i32,
+    pub step: i32,
+}
+
+/// A table of up to `HeightSteps::MAX_LEN` entries sorted by increasing `max_height`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialization", serde(try_from = "Vec<HeightStep>", into = "Vec<HeightStep>"))]
+pub struct HeightSteps {
+    steps: [HeightStep; HeightSteps::MAX_LEN],
+    len: u8,
+}
+
+impl HeightSteps {
+    pub const MAX_LEN: usize = 8;
+
+    /// Returns `None` if the table is empty or longer than `MAX_LEN`, if a step or a
+    /// `max_height` isn't positive, or if the `max_height`s aren't strictly increasing.
+    pub const fn new(steps: &[HeightStep]) -> Option<Self> {
+        if steps.is_empty() || steps.len() > HeightSteps::MAX_LEN {
+            return None;
+        }
+
+        let mut table = [HeightStep { max_height: 0, step: 0 }; HeightSteps::MAX_LEN];
+        let mut i = 0;
+        while i < steps.len() {
+            let step = steps[i];
+            if step.step <= 0 || step.max_height <= 0 {
+                return None;
+            }
+            if i > 0 && step.max_height <= steps[i - 1].max_height {
+                return None;
+            }
+            table[i] = step;
+            i += 1;
+        }
+
+        Some(HeightSteps { steps: table, len: steps.len() as u8 })
+    }
+
+    pub fn as_slice(&self) -> &[HeightStep] {
+        &self.steps[..self.len as usize]
+    }
+}
+
+impl std::convert::TryFrom<Vec<HeightStep>> for HeightSteps {
+    type Error = &'static str;
+
+    fn try_from(steps: Vec<HeightStep>) -> Result<Self, Self::Error> {
+        HeightSteps::new(&steps).ok_or("invalid shelf height steps")
+    }
+}
+
+impl From<HeightSteps> for Vec<HeightStep> {
+    fn from(steps: HeightSteps) -> Self {
+        steps.as_slice().to_vec()
+    }
+}
+
+impl ShelfHeights {
+    /// Steps of 8, 16, 32 or 64 pixels, which works well for glyphs and small images.
+    pub const DEFAULT: Self = ShelfHeights::Steps(match HeightSteps::new(&[
+        HeightStep { max_height: 31, step: 8 },
+        HeightStep { max_height: 127, step: 16 },
+        HeightStep { max_height: 511, step: 32 },
+        HeightStep { max_height: i32::MAX, step: 64 },
+    ]) {
+        Some(steps) => steps,
+        None => panic!(),
+    });
+
+    fn shelf_height(&self, mut size: u32) -> u32 {
+        let steps = match self {
+            ShelfHeights::Exact => {
+                return size;
+            }
+            ShelfHeights::Steps(steps) => steps.as_slice(),
+        };
+
+        // Entries are validated, `max_height` and `step` are positive.
+        let step = steps.iter()
+            .find(|step| size <= step.max_height as u32)
+            .unwrap_or(&steps[steps.len() - 1])
+            .step as u32;
+
+        let rem = size % step;
+        if rem > 0 {
+            size += step - rem;
+        }
+
+        size
+    }
+}
+
+impl Default for ShelfHeights {
+    fn default() -> Self {
+        ShelfHeights::DEFAULT
+    }
+}
+
+/// How many bins a new shelf is split into.
+///
+/// Bins are never narrower than the item that creates the shelf.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum ShelfBins {
+    /// From 1 to 16 bins depending on the ratio between the width of the atlas and the
+    /// largest side of the item that creates the shelf.
+    #[default]
+    Auto,
+    /// The same number of bins for every shelf.
+    Count(u32),
+    /// As many bins of at least this width as fit in the shelf.
+    Width(i32),
+}
+
 impl Default for AllocatorOptions {
     fn default() -> Self {
         AllocatorOptions::DEFAULT
@@ -116,28 +429,98 @@ impl Default for AllocatorOptions {
 /// it might cause fragmentation issues when deallocation leaves a lot of empty shelves in the middle but
 /// it doesn't matter much in practice when items have similar heights since most new items will fill the
 /// gaps instead of creating new shelves.
+///
+/// The id layout is `Compact` by default, see `WideAtlasAllocator` for larger atlases.
 #[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
-pub struct AtlasAllocator {
-    shelves: Vec<Shelf>,
-    bins: Vec<Bin>,
-    available_height: u16,
-    width: u16,
-    height: u16,
-    first_unallocated_bin: BinIndex,
+#[derive(Clone)]
+pub struct AtlasAllocator<L: IdLayout = Compact> {
+    shelves: Vec<Shelf<L::Coord>>,
+    bins: Vec<Bin<L::Coord>>,
+    available_height: L::Coord,
+    width: L::Coord,
+    height: L::Coord,
+    first_unallocated_bin: BinIndex<L::Coord>,
     flip_xy: bool,
     alignment: Size,
+    #[cfg_attr(feature = "serialization", serde(default))]
+    shelf_heights: ShelfHeights,
+    #[cfg_attr(feature = "serialization", serde(default))]
+    shelf_bins: ShelfBins,
+}
+
+/// A shelf allocator with the `Wide` id layout.
+pub type WideAtlasAllocator = AtlasAllocator<Wide>;
+
+/// A `WideAtlasAllocator` that can be used through the crate's `AtlasAllocator` trait,
+/// for example in an `AllocatorList`.
+///
+/// The ids of the trait are 32 bits, so they index a table of the 64-bit ids of the
+/// allocator instead of containing them.
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct WideShelfAllocator {
+    atlas: WideAtlasAllocator,
+    ids: FreeList<WideAllocId>,
+}
+
+impl WideShelfAllocator {
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        WideShelfAllocator {
+            atlas: AtlasAllocator::wide(size, options),
+            ids: FreeList::new(),
+        }
+    }
+
+    pub fn atlas(&self) -> &WideAtlasAllocator {
+        &self.atlas
+    }
+
+    /// Allocate a rectangle and return the index of its id in the table.
+    pub fn allocate(&mut self, size: Size) -> Option<(FreeListHandle, Rectangle)> {
+        let (id, rectangle) = self.atlas.allocate(size)?;
+
+        Some((self.ids.add_with_value(id), rectangle))
+    }
+
+    pub fn deallocate(&mut self, handle: FreeListHandle) {
+        self.atlas.deallocate(self.ids[handle]);
+        self.ids.remove(handle);
+    }
+
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        self.ids.clear();
+    }
 }
 
 impl AtlasAllocator {
     pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
-        assert!(size.width < u16::MAX as i32);
-        assert!(size.height < u16::MAX as i32);
+        Self::with_layout(size, options)
+    }
+
+    pub fn new(size: Size) -> Self {
+        Self::with_options(size, &AllocatorOptions::DEFAULT)
+    }
+}
+
+impl AtlasAllocator<Wide> {
+    /// Create an allocator with the `Wide` id layout.
+    pub fn wide(size: Size, options: &AllocatorOptions) -> Self {
+        Self::with_layout(size, options)
+    }
+}
+
+impl<L: IdLayout> AtlasAllocator<L> {
+    fn with_layout(size: Size, options: &AllocatorOptions) -> Self {
+        assert!(size.width >= 0 && size.width <= L::MAX_SIDE);
+        assert!(size.height >= 0 && size.height <= L::MAX_SIDE);
 
         let (width, height) = if options.vertical_bins {
-            (size.height as u16, size.width as u16)
+            (size.height as u32, size.width as u32)
         } else {
-            (size.width as u16, size.height as u16)
+            (size.width as u32, size.height as u32)
         };
+        let (width, height) = (L::Coord::from_u32(width), L::Coord::from_u32(height));
 
         AtlasAllocator {
             shelves: Vec::new(),
@@ -148,48 +531,71 @@ impl AtlasAllocator {
             first_unallocated_bin: BinIndex::INVALID,
             flip_xy: options.vertical_bins,
             alignment: options.alignment,
+            shelf_heights: options.shelf_heights,
+            shelf_bins: options.shelf_bins,
         }
     }
 
-    pub fn new(size: Size) -> Self {
-        Self::with_options(size, &AllocatorOptions::DEFAULT)
-    }
-
     pub fn clear(&mut self) {
         self.shelves.clear();
         self.bins.clear();
+        self.available_height = self.height;
         self.first_unallocated_bin = BinIndex::INVALID;
     }
 
     pub fn size(&self) -> Size {
-        size2(self.width as i32, self.height as i32)
+        let (width, height) = convert_coordinates(self.flip_xy, self.width, self.height);
+        size2(width.to_u32() as i32, height.to_u32() as i32)
     }
 
     pub fn is_empty(&self) -> bool {
         self.shelves.is_empty()
     }
 
+    /// The area of the atlas that is covered by the allocated parts of the bins.
+    ///
+    /// Since bins don't keep track of the space freed by individual items until they
+    /// are empty, this can be larger than the sum of the live allocations.
+    pub fn allocated_space(&self) -> i32 {
+        let mut space = 0;
+        for shelf in &self.shelves {
+            let mut bin_index = shelf.first_bin;
+            while bin_index != BinIndex::INVALID {
+                let bin = &self.bins[bin_index.to_usize()];
+                space += (shelf.bin_width - bin.free_space).to_u32() as i32 * shelf.height.to_u32() as i32;
+                bin_index = bin.next;
+            }
+        }
+
+        space
+    }
+
     /// Allocate a rectangle in the atlas.
-    pub fn allocate(&mut self, mut requested_size: Size) -> Option<(AllocId, Rectangle)> {
+    pub fn allocate(&mut self, mut requested_size: Size) -> Option<(L::Id, Rectangle)> {
         if requested_size.is_empty() {
             return None;
         }
-        if requested_size.width > u16::MAX as i32 || requested_size.height > u16::MAX as i32 {
+        let size = self.size();
+        if requested_size.width > size.width || requested_size.height > size.height {
             return None;
         }
 
         adjust_size(self.alignment.width, &mut requested_size.width);
         adjust_size(self.alignment.height, &mut requested_size.height);
 
-        let (w, h) = convert_coordinates(self.flip_xy, requested_size.width as u16, requested_size.height as u16);
+        let (w, h) = convert_coordinates(
+            self.flip_xy,
+            L::Coord::from_u32(requested_size.width as u32),
+            L::Coord::from_u32(requested_size.height as u32),
+        );
 
         let mut selected_shelf = std::usize::MAX;
         let mut selected_bin = BinIndex::INVALID;
-        let mut best_waste = u16::MAX;
+        let mut best_waste = L::Coord::MAX;
 
         let can_add_shelf = self.available_height >= h
-            && self.shelves.len() < MAX_SHELF_COUNT
-            && self.bins.len() < MAX_BIN_COUNT;
+            && self.shelves.len() < L::Coord::MAX.to_u32() as usize
+            && self.bins.len() < max_bin_count::<L>();
 
         'shelves: for (shelf_index, shelf) in self.shelves.iter().enumerate() {
             if shelf.height < h || shelf.bin_width < w {
@@ -205,8 +611,8 @@ impl AtlasAllocator {
             while bin_index != BinIndex::INVALID {
                 let bin = &self.bins[bin_index.to_usize()];
 
-                if bin.free_space >= w && bin.item_count < MAX_ITEMS_PER_BIN {
-                    if y_waste == 0 && bin.free_space == w {
+                if bin.free_space >= w && bin.item_count.to_u32() < max_items_per_bin::<L>() {
+                    if y_waste == L::Coord::ZERO && bin.free_space == w {
                         selected_shelf = shelf_index;
                         selected_bin = bin_index;
 
@@ -244,7 +650,7 @@ impl AtlasAllocator {
         return  None;
     }
 
-    fn alloc_from_bin(&mut self, shelf_index: usize, bin_index: BinIndex, width: u16) -> Option<(AllocId, Rectangle)> {
+    fn alloc_from_bin(&mut self, shelf_index: usize, bin_index: BinIndex<L::Coord>, width: L::Coord) -> Option<(L::Id, Rectangle)> {
         let shelf = &mut self.shelves[shelf_index];
         let bin = &mut self.bins[bin_index.to_usize()];
 
@@ -257,55 +663,53 @@ impl AtlasAllocator {
         let (max_x, max_y) = convert_coordinates(self.flip_xy, max_x, max_y);
 
         bin.free_space -= width;
-        bin.refcount += 1;
-        bin.item_count += 1;
+        bin.refcount += L::Coord::ONE;
+        bin.item_count += L::Coord::ONE;
 
-        let id = AllocId(
-            (bin_index.0 as u32) & BIN_MASK
-            | ((bin.item_count as u32) << 12) & ITEM_MASK
-            | (bin.generation.0 as u32) << 24
-        );
+        let id = pack_id::<L>(bin_index, bin.item_count, bin.generation);
 
         let rectangle = Rectangle {
-            min: point2(min_x as i32, min_y as i32),
-            max: point2(max_x as i32, max_y as i32),
+            min: point2(min_x.to_u32() as i32, min_y.to_u32() as i32),
+            max: point2(max_x.to_u32() as i32, max_y.to_u32() as i32),
         };
 
         Some((id, rectangle))
     }
 
-    fn add_shelf(&mut self, width: u16, height: u16) -> usize {
-        let height = shelf_height(height).min(self.available_height);
+    fn add_shelf(&mut self, width: L::Coord, height: L::Coord) -> usize {
+        // Rounding up can't go past the available height, so the result fits.
+        let height = self.shelf_heights.shelf_height(height.to_u32()).min(self.available_height.to_u32());
+        let height = L::Coord::from_u32(height);
         let num_bins = self.num_bins(width, height);
-        let bin_width = self.height / num_bins;
+        let bin_width = self.width / num_bins;
         let y = self.height - self.available_height;
         self.available_height -= height;
 
         let shelf_index = self.shelves.len();
 
         // Initialize the bins for our new shelf.
-        let mut x = 0;
+        let mut x = L::Coord::ZERO;
         let mut bin_next = BinIndex::INVALID;
-        for _ in 0..num_bins {
+        for _ in 0..num_bins.to_u32() {
             let mut bin = Bin {
                 next: bin_next,
                 x,
                 free_space: bin_width,
-                refcount: 0,
-                shelf: shelf_index as u16,
-                generation: Wrapping(0),
-                item_count: 0,
+                refcount: L::Coord::ZERO,
+                shelf: L::Coord::from_u32(shelf_index as u32),
+                generation: L::Coord::ZERO,
+                item_count: L::Coord::ZERO,
             };
 
             let mut bin_index = self.first_unallocated_bin;
             x += bin_width;
 
             if bin_index == BinIndex::INVALID {
-                bin_index = BinIndex(self.bins.len() as u16);
+                bin_index = BinIndex(L::Coord::from_u32(self.bins.len() as u32));
                 self.bins.push(bin);
             } else {
                 let idx = bin_index.to_usize();
-                bin.generation = self.bins[idx].generation + Wrapping(1);
+                bin.generation = self.bins[idx].generation.wrapping_inc();
                 self.first_unallocated_bin = self.bins[idx].next;
 
                 self.bins[idx] = bin;
@@ -332,10 +736,10 @@ impl AtlasAllocator {
     /// The squashed shelves are not removed, their height is just set to zero so no item
     /// can go in, and they will be garbage-collected whenever there's no shelf above them.
     /// For simplicity, the bin width is not modified.
-    fn coalesce_shelves(&mut self, w: u16, h: u16) -> (usize, BinIndex) {
+    fn coalesce_shelves(&mut self, w: L::Coord, h: L::Coord) -> (usize, BinIndex<L::Coord>) {
         let len = self.shelves.len();
         let mut coalesce_range = None;
-        let mut coalesced_height = 0;
+        let mut coalesced_height = L::Coord::ZERO;
 
         'outer: for shelf_index in 0..len {
             if self.shelves[shelf_index].bin_width < w {
@@ -365,8 +769,12 @@ impl AtlasAllocator {
         }
 
         if let Some(range) = coalesce_range {
+            // Move the squashed shelves to the bottom of the coalesced one so that
+            // the shelves stay contiguous.
+            let end_y = self.shelves[range.start].y + coalesced_height;
             for i in range.start + 1 .. range.end {
-                self.shelves[i].height = 0;
+                self.shelves[i].y = end_y;
+                self.shelves[i].height = L::Coord::ZERO;
             }
 
             let shelf_index = range.start;
@@ -379,18 +787,25 @@ impl AtlasAllocator {
         (0, BinIndex::INVALID)
     }
 
-    fn num_bins(&self, width: u16, height: u16) -> u16 {
-        match self.width / u16::max(width, height) {
-            0 ..= 4 => 1,
-            5 ..= 15 => 2,
-            16 ..= 64 => 4,
-            65 ..= 256 => 8,
-            _ => 16,
-        }.min((MAX_BIN_COUNT - self.bins.len()) as u16)
+    fn num_bins(&self, width: L::Coord, height: L::Coord) -> L::Coord {
+        let (shelf_width, width, height) = (self.width.to_u32(), width.to_u32(), height.to_u32());
+        let num_bins = match self.shelf_bins {
+            ShelfBins::Auto => match shelf_width / u32::max(width, height) {
+                0 ..= 4 => 1,
+                5 ..= 15 => 2,
+                16 ..= 64 => 4,
+                65 ..= 256 => 8,
+                _ => 16,
+            },
+            ShelfBins::Count(count) => count.min(shelf_width / width),
+            ShelfBins::Width(bin_width) => shelf_width / (bin_width.max(1) as u32).max(width),
+        };
+
+        L::Coord::from_u32(num_bins.max(1).min((max_bin_count::<L>() - self.bins.len()) as u32))
     }
 
     /// Deallocate a rectangle in the atlas.
-    pub fn deallocate(&mut self, id: AllocId) {
+    pub fn deallocate(&mut self, id: L::Id) {
         if self.deallocate_from_bin(id) {
             self.cleanup_shelves();
         }
@@ -399,26 +814,25 @@ impl AtlasAllocator {
     /// Returns true if we should garbage-collect the shelves as a result of
     /// removing this element (we deallocated the last item from the bin on
     /// the top-most shelf).
-    fn deallocate_from_bin(&mut self, id: AllocId) -> bool {
-        let bin_index = (id.0 & BIN_MASK) as usize;
-        let generation = ((id.0 & GEN_MASK) >> 24 ) as u8;
+    fn deallocate_from_bin(&mut self, id: L::Id) -> bool {
+        let (bin_index, generation) = unpack_id::<L>(id);
 
         let bin = &mut self.bins[bin_index];
 
-        let expected_generation = bin.generation.0;
+        let expected_generation = bin.generation.to_u32() & generation_mask::<L>();
         assert_eq!(generation, expected_generation);
 
-        assert!(bin.refcount > 0);
-        bin.refcount -= 1;
+        assert!(bin.refcount > L::Coord::ZERO);
+        bin.refcount -= L::Coord::ONE;
 
-        let shelf = &self.shelves[bin.shelf as usize];
+        let shelf = &self.shelves[bin.shelf.to_u32() as usize];
 
-        let bin_is_empty = bin.refcount == 0;
+        let bin_is_empty = bin.refcount == L::Coord::ZERO;
         if bin_is_empty {
             bin.free_space = shelf.bin_width;
         }
 
-        bin_is_empty && bin.shelf as usize == self.shelves.len() - 1
+        bin_is_empty && bin.shelf.to_u32() as usize == self.shelves.len() - 1
     }
 
     fn cleanup_shelves(&mut self) {
@@ -431,7 +845,7 @@ impl AtlasAllocator {
                 while bin_index != BinIndex::INVALID {
                     let bin = &self.bins[bin_index.to_usize()];
 
-                    if bin.refcount != 0 {
+                    if bin.refcount != L::Coord::ZERO {
                         return;
                     }
 
@@ -456,6 +870,101 @@ impl AtlasAllocator {
         }
     }
 
+    /// Check the internal invariants of the allocator and panic if any of them
+    /// is violated.
+    ///
+    /// This is meant to help with debugging and testing, it is too slow to be
+    /// called often in production code.
+    pub fn validate(&self) {
+        // Shelves are stacked without gaps from the top of the atlas.
+        let mut y = L::Coord::ZERO;
+        for (shelf_index, shelf) in self.shelves.iter().enumerate() {
+            assert_eq!(shelf.y, y, "shelf {} is not contiguous with the previous one", shelf_index);
+            y += shelf.height;
+        }
+        assert_eq!(y + self.available_height, self.height, "inconsistent available height");
+
+        // Each bin is either in exactly one shelf or in the free list.
+        let mut owner = vec![None; self.bins.len()];
+
+        for (shelf_index, shelf) in self.shelves.iter().enumerate() {
+            assert!(shelf.first_bin != BinIndex::INVALID, "shelf {} has no bin", shelf_index);
+            let mut bin_index = shelf.first_bin;
+            while bin_index != BinIndex::INVALID {
+                let idx = bin_index.to_usize();
+                assert!(owner[idx].is_none(), "bin {} is linked twice", idx);
+                owner[idx] = Some(shelf_index);
+
+                let bin = &self.bins[idx];
+                assert_eq!(bin.shelf.to_u32() as usize, shelf_index, "bin {} points to the wrong shelf", idx);
+                assert!(bin.x + shelf.bin_width <= self.width, "bin {} is out of bounds", idx);
+                assert!(bin.free_space <= shelf.bin_width, "bin {} has too much free space", idx);
+                assert!(bin.refcount <= bin.item_count, "bin {} has more live items than allocated ones", idx);
+                if bin.refcount == L::Coord::ZERO {
+                    assert_eq!(bin.free_space, shelf.bin_width, "empty bin {} was not reset", idx);
+                } else {
+                    assert!(shelf.bin_width - bin.free_space >= bin.refcount, "bin {} has more items than space", idx);
+                }
+
+                bin_index = bin.next;
+            }
+        }
+
+        let mut bin_index = self.first_unallocated_bin;
+        while bin_index != BinIndex::INVALID {
+            let idx = bin_index.to_usize();
+            assert!(owner[idx].is_none(), "free bin {} is still in use", idx);
+            owner[idx] = Some(usize::MAX);
+            bin_index = self.bins[idx].next;
+        }
+
+        for (idx, owner) in owner.iter().enumerate() {
+            assert!(owner.is_some(), "bin {} was leaked", idx);
+        }
+    }
+
+    /// Number of allocated items, for testing purposes.
+    #[cfg(test)]
+    fn live_items(&self) -> usize {
+        let mut count = 0;
+        for shelf in &self.shelves {
+            let mut bin_index = shelf.first_bin;
+            while bin_index != BinIndex::INVALID {
+                let bin = &self.bins[bin_index.to_usize()];
+                count += bin.refcount.to_u32() as usize;
+                bin_index = bin.next;
+            }
+        }
+
+        count
+    }
+
+    /// Invoke a callback for the allocated part of each bin.
+    ///
+    /// Bins don't keep track of individual items, so each rectangle can contain
+    /// several allocations.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for shelf in &self.shelves {
+            let mut bin_index = shelf.first_bin;
+            while bin_index != BinIndex::INVALID {
+                let bin = &self.bins[bin_index.to_usize()];
+                let used = shelf.bin_width - bin.free_space;
+                if used > L::Coord::ZERO && shelf.height > L::Coord::ZERO {
+                    let (min_x, min_y) = convert_coordinates(self.flip_xy, bin.x, shelf.y);
+                    let (max_x, max_y) = convert_coordinates(self.flip_xy, bin.x + used, shelf.y + shelf.height);
+                    callback(&Rectangle {
+                        min: point2(min_x.to_u32() as i32, min_y.to_u32() as i32),
+                        max: point2(max_x.to_u32() as i32, max_y.to_u32() as i32),
+                    });
+                }
+                bin_index = bin.next;
+            }
+        }
+    }
+
     fn shelf_is_empty(&self, idx: usize) -> bool {
         let shelf = &self.shelves[idx];
         let mut bin_index = shelf.first_bin;
@@ -463,7 +972,7 @@ impl AtlasAllocator {
         while bin_index != BinIndex::INVALID {
             let bin = &self.bins[bin_index.to_usize()];
 
-            if bin.refcount != 0 {
+            if bin.refcount != L::Coord::ZERO {
                 return false;
             }
 
@@ -474,7 +983,7 @@ impl AtlasAllocator {
     }
 }
 
-fn convert_coordinates(flip_xy: bool, x: u16, y: u16) -> (u16, u16) {
+fn convert_coordinates<C>(flip_xy: bool, x: C, y: C) -> (C, C) {
     if flip_xy {
         (y, x)
     } else {
@@ -483,37 +992,40 @@ fn convert_coordinates(flip_xy: bool, x: u16, y: u16) -> (u16, u16) {
 }
 
 
-fn shelf_height(mut size: u16) -> u16 {
-    let alignment = match size {
-        0 ..= 31 => 8,
-        32 ..= 127 => 16,
-        128 ..= 511 => 32,
-        _ => 64,
-    };
-
-    let rem = size % alignment;
-    if rem > 0 {
-        size += alignment - rem;
-    }
 
-    size
+/// Dump a visual representation of the atlas in SVG format.
+pub fn dump_svg<L: IdLayout>(atlas: &AtlasAllocator<L>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    dump_svg_with_options(atlas, &SvgOptions { captions: false, .. SvgOptions::DEFAULT }, &[], output)
 }
 
-
-/// Dump a visual representation of the atlas in SVG format.
-pub fn dump_svg(atlas: &AtlasAllocator, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+/// Dump a visual representation of the atlas in SVG format, with the provided allocations
+/// drawn on top of it.
+pub fn dump_svg_with_options<L: IdLayout>(
+    atlas: &AtlasAllocator<L>,
+    options: &SvgOptions,
+    allocations: &[SvgAllocation],
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
     use svg_fmt::*;
 
+    let size = atlas.size();
+    let caption_height = if options.captions { svg::CAPTION_HEIGHT } else { 0.0 };
+
     writeln!(
         output,
         "{}",
         BeginSvg {
-            w: atlas.width as f32,
-            h: atlas.height as f32
+            w: size.width as f32,
+            h: size.height as f32 + caption_height,
         }
     )?;
 
-    dump_into_svg(atlas, None, output)?;
+    dump_into_svg_with_options(atlas, None, options, allocations, output)?;
+
+    if options.captions {
+        let occupancy = svg::occupancy(atlas.allocated_space() as i64, size.area() as i64);
+        svg::write_caption(0.0, size.height as f32, &format!("{:.1}% occupied", occupancy), output)?;
+    }
 
     writeln!(output, "{}", EndSvg)
 }
@@ -522,66 +1034,75 @@ pub fn dump_svg(atlas: &AtlasAllocator, output: &mut dyn std::io::Write) -> std:
 /// SVG document, so that it can be included in a larger document.
 ///
 /// If a rectangle is provided, translate and scale the output to fit it.
-pub fn dump_into_svg(atlas: &AtlasAllocator, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+pub fn dump_into_svg<L: IdLayout>(atlas: &AtlasAllocator<L>, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    dump_into_svg_with_options(atlas, rect, &SvgOptions::DEFAULT, &[], output)
+}
+
+/// Same as `dump_into_svg`, with the provided allocations drawn on top of the atlas.
+pub fn dump_into_svg_with_options<L: IdLayout>(
+    atlas: &AtlasAllocator<L>,
+    rect: Option<&Rectangle>,
+    options: &SvgOptions,
+    allocations: &[SvgAllocation],
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
     use svg_fmt::*;
 
+    let size = atlas.size();
     let (sx, sy, tx, ty) = if let Some(rect) = rect {
         (
-            rect.size().width as f32 / atlas.width as f32,
-            rect.size().height as f32 / atlas.height as f32,
+            rect.size().width as f32 / size.width as f32,
+            rect.size().height as f32 / size.height as f32,
             rect.min.x as f32,
             rect.min.y as f32,
         )
     } else {
-        (1.0, 1.0, 0.0, 0.0)        
+        (1.0, 1.0, 0.0, 0.0)
     };
 
     writeln!(
         output,
         r#"    {}"#,
-        rectangle(tx, ty, atlas.width as f32 * sx, atlas.height as f32 * sy)
+        rectangle(tx, ty, size.width as f32 * sx, size.height as f32 * sy)
             .fill(rgb(40, 40, 40))
             .stroke(Stroke::Color(black(), 1.0))
     )?;
 
+    let allocated_color = svg::allocated_color(options, svg::AllocatorKind::Shelf);
+
+    // Shelves and bins are stored in flipped coordinates when using vertical bins.
+    let flip = atlas.flip_xy;
+    let mut write_rect = |x: L::Coord, y: L::Coord, w: L::Coord, h: L::Coord, color| {
+        let (x, y) = convert_coordinates(flip, x.to_u32(), y.to_u32());
+        let (w, h) = convert_coordinates(flip, w.to_u32(), h.to_u32());
+        writeln!(
+            output,
+            r#"    {}"#,
+            rectangle(x as f32 * sx + tx, y as f32 * sy + ty, w as f32 * sx, h as f32 * sy)
+                .fill(color)
+                .stroke(Stroke::Color(black(), 1.0))
+        )
+    };
 
     for shelf in &atlas.shelves {
         let mut bin_index = shelf.first_bin;
 
-        let y = shelf.y as f32 * sy + ty;
-        let h = shelf.height as f32 * sy;
         while bin_index != BinIndex::INVALID {
             let bin = &atlas.bins[bin_index.to_usize()];
 
-            let x = bin.x as f32 * sx + tx;
-            let w = (shelf.bin_width - bin.free_space) as f32 * sx;
-
-            writeln!(
-                output,
-                r#"    {}"#,
-                rectangle(x, y, w, h)
-                    .fill(rgb(70, 70, 180))
-                    .stroke(Stroke::Color(black(), 1.0))
-            )?;
-
-            if bin.free_space > 0 {
-                let x_free = x + w;
-                let w_free = bin.free_space as f32 * sx;
-
-                writeln!(
-                    output,
-                    r#"    {}"#,
-                    rectangle(x_free, y, w_free, h)
-                        .fill(rgb(50, 50, 50))
-                        .stroke(Stroke::Color(black(), 1.0))
-                )?;
+            let used = shelf.bin_width - bin.free_space;
+
+            write_rect(bin.x, shelf.y, used, shelf.height, allocated_color)?;
+
+            if bin.free_space > L::Coord::ZERO {
+                write_rect(bin.x + used, shelf.y, bin.free_space, shelf.height, rgb(50, 50, 50))?;
             }
 
             bin_index = bin.next;
         }
     }
 
-    Ok(())
+    svg::dump_allocations_into_svg(size, rect, svg::AllocatorKind::Shelf, options, allocations, output)
 }
 
 fn adjust_size(alignment: i32, size: &mut i32) {
@@ -670,3 +1191,136 @@ fn test_coalesce_shelves() {
 
     assert!(atlas.is_empty());
 }
+
+#[test]
+fn random_operations() {
+    let options = [
+        AllocatorOptions::DEFAULT,
+        AllocatorOptions { vertical_bins: true, .. AllocatorOptions::DEFAULT },
+        AllocatorOptions { alignment: size2(4, 8), .. AllocatorOptions::DEFAULT },
+        AllocatorOptions { shelf_heights: ShelfHeights::Exact, shelf_bins: ShelfBins::Count(3), .. AllocatorOptions::DEFAULT },
+    ];
+    let sizes = [size2(256, 256), size2(1000, 300), size2(300, 1000)];
+
+    for (i, options) in options.iter().enumerate() {
+        for (j, &size) in sizes.iter().enumerate() {
+            let mut atlas = AtlasAllocator::with_options(size, options);
+            crate::check_random_operations(&mut atlas, (i * 3 + j) as u64, 2000, size2(120, 120), &|atlas, live| {
+                atlas.validate();
+                assert_eq!(atlas.live_items(), live);
+            });
+
+            // Only the allocation sequence is checked with the wide layout.
+            let mut wide = AtlasAllocator::wide(size, options);
+            let mut ids = Vec::new();
+            for _ in 0..50 {
+                if let Some((id, _)) = wide.allocate(size2(20, 30)) {
+                    ids.push(id);
+                }
+            }
+            wide.validate();
+            for id in ids {
+                wide.deallocate(id);
+            }
+            assert!(wide.is_empty());
+
+            // Clearing must give back the whole atlas.
+            for _ in 0..10 {
+                atlas.allocate(size2(50, 50)).unwrap();
+            }
+            atlas.clear();
+            atlas.validate();
+            let aligned_size = size2(size.width - size.width % 8, size.height - size.height % 8);
+            assert!(atlas.allocate(aligned_size).is_some());
+        }
+    }
+}
+
+#[test]
+fn wide_ids() {
+    use std::collections::HashSet;
+
+    // Larger than what the compact layout supports.
+    let mut atlas = WideAtlasAllocator::wide(size2(100_000, 70_000), &AllocatorOptions::DEFAULT);
+    let (big, rect) = atlas.allocate(size2(80_000, 66_048)).unwrap();
+    assert_eq!(rect.size(), size2(80_000, 66_048));
+    atlas.validate();
+    atlas.deallocate(big);
+    assert!(atlas.is_empty());
+
+    // Reusing the same bins over and over wraps the generation of compact ids much sooner.
+    fn churn<L: IdLayout>(mut atlas: AtlasAllocator<L>) -> bool {
+        let mut ids = HashSet::new();
+        let mut unique = true;
+        for _ in 0..5000 {
+            let id = atlas.allocate(size2(10, 10)).unwrap().0;
+            unique &= ids.insert(id);
+            atlas.deallocate(id);
+        }
+        atlas.validate();
+
+        unique
+    }
+
+    assert!(!churn(AtlasAllocator::new(size2(1000, 1000))));
+    assert!(churn(AtlasAllocator::wide(size2(1000, 1000), &AllocatorOptions::DEFAULT)));
+
+    // The compact layout doesn't pay for the wide one.
+    assert_eq!(std::mem::size_of::<AllocId>(), 4);
+    assert_eq!(std::mem::size_of::<Bin<u16>>(), 14);
+}
+
+#[test]
+fn shelf_policies() {
+    let shelf_count = |atlas: &AtlasAllocator| atlas.shelves.len();
+    let bin_count = |atlas: &AtlasAllocator| {
+        let mut count = 0;
+        let mut bin_index = atlas.shelves[0].first_bin;
+        while bin_index != BinIndex::INVALID {
+            count += 1;
+            bin_index = atlas.bins[bin_index.to_usize()].next;
+        }
+        count
+    };
+
+    // With the default steps, 10 and 14 pixels tall items share a 16 pixels shelf.
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    assert_eq!(atlas.allocate(size2(10, 10)).unwrap().1.height(), 16);
+    atlas.allocate(size2(10, 14)).unwrap();
+    assert_eq!(shelf_count(&atlas), 1);
+    assert_eq!(bin_count(&atlas), 4);
+
+    let exact = AllocatorOptions { shelf_heights: ShelfHeights::Exact, .. AllocatorOptions::DEFAULT };
+    let mut atlas = AtlasAllocator::with_options(size2(256, 256), &exact);
+    assert_eq!(atlas.allocate(size2(10, 10)).unwrap().1.height(), 10);
+    atlas.allocate(size2(10, 14)).unwrap();
+    assert_eq!(shelf_count(&atlas), 2);
+
+    let step = |max_height, step| HeightStep { max_height, step };
+    assert!(HeightSteps::new(&[]).is_none());
+    assert!(HeightSteps::new(&[step(12, 12), step(12, 50)]).is_none());
+    assert!(HeightSteps::new(&[step(-1, 12)]).is_none());
+    assert!(HeightSteps::new(&[step(12, 0)]).is_none());
+    assert!(HeightSteps::new(&[step(1, 1); HeightSteps::MAX_LEN + 1]).is_none());
+
+    let steps = AllocatorOptions {
+        shelf_heights: ShelfHeights::Steps(HeightSteps::new(&[step(12, 12), step(100, 50)]).unwrap()),
+        shelf_bins: ShelfBins::Count(3),
+        .. AllocatorOptions::DEFAULT
+    };
+    let mut atlas = AtlasAllocator::with_options(size2(300, 300), &steps);
+    assert_eq!(atlas.allocate(size2(10, 5)).unwrap().1.height(), 12);
+    assert_eq!(bin_count(&atlas), 3);
+    assert_eq!(atlas.allocate(size2(10, 60)).unwrap().1.height(), 100);
+    // Past the last entry, its step is used.
+    assert_eq!(atlas.allocate(size2(10, 120)).unwrap().1.height(), 150);
+    // Bins are never narrower than the item that creates the shelf.
+    assert!(atlas.allocate(size2(200, 10)).is_some());
+    atlas.validate();
+
+    let wide_bins = AllocatorOptions { shelf_bins: ShelfBins::Width(100), .. AllocatorOptions::DEFAULT };
+    let mut atlas = AtlasAllocator::with_options(size2(256, 256), &wide_bins);
+    atlas.allocate(size2(10, 10)).unwrap();
+    assert_eq!(bin_count(&atlas), 2);
+    atlas.validate();
+}
@@ -0,0 +1,433 @@
+//! Manifests describing the content of an `AllocatorList` in formats that game engines
+//! and font renderers can load directly.
+//!
+//! Each layer of each texture is a page. Pages are numbered from zero in the order of their
+//! texture ids, then of their layers. Each page also records its `TextureId`, its layer and
+//! its own size, which differs from the size of the list when textures grow or are standalone.
+//!
+//! TexturePacker manifests name the image of the first page in `meta.image` for loaders that
+//! only support one, and the image of its page in each frame.
+
+use crate::{AllocatorList, AtlasAllocator, Handle, Rectangle, Size, TextureId};
+use std::io::{Error, ErrorKind, Write};
+
+/// A named allocation to export.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExportEntry<'l> {
+    pub name: &'l str,
+    pub handle: Handle,
+    /// The rectangle returned by the allocator.
+    pub rectangle: Rectangle,
+    /// The requested size, which is exported instead of the size of the rectangle since
+    /// allocators can round it up.
+    pub size: Size,
+    /// The layer of the texture that holds the allocation.
+    pub layer: u16,
+}
+
+impl<'l> ExportEntry<'l> {
+    /// The part of the allocated rectangle that holds the requested size.
+    fn frame(&self) -> Rectangle {
+        Rectangle {
+            min: self.rectangle.min,
+            max: self.rectangle.min + self.size.to_vector(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExportOptions<'l> {
+    /// File name of the image of each page. `{}` is replaced with the page index.
+    ///
+    /// Default value: "atlas-{}.png".
+    pub image_name: &'l str,
+}
+
+impl<'l> ExportOptions<'l> {
+    pub const DEFAULT: Self = ExportOptions {
+        image_name: "atlas-{}.png",
+    };
+}
+
+impl<'l> Default for ExportOptions<'l> {
+    fn default() -> Self {
+        ExportOptions::DEFAULT
+    }
+}
+
+struct Pages {
+    ids: Vec<TextureId>,
+    layers: Vec<u16>,
+    sizes: Vec<Size>,
+}
+
+impl Pages {
+    fn new<A: AtlasAllocator>(atlas: &AllocatorList<A>) -> Self {
+        let mut textures = atlas.texture_ids();
+        textures.sort_by_key(TextureId::serialize);
+
+        let mut pages = Pages { ids: Vec::new(), layers: Vec::new(), sizes: Vec::new() };
+        for id in textures {
+            let size = atlas.texture_size(id).unwrap();
+            for layer in 0..atlas.texture_layers(id).unwrap() {
+                pages.ids.push(id);
+                pages.layers.push(layer);
+                pages.sizes.push(size);
+            }
+        }
+
+        pages
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// The size of the first page, or of the list if there are no pages.
+    fn first_size<A: AtlasAllocator>(&self, atlas: &AllocatorList<A>) -> Size {
+        self.sizes.first().copied().unwrap_or_else(|| atlas.size())
+    }
+
+    fn index(&self, entry: &ExportEntry) -> std::io::Result<usize> {
+        let texture = entry.handle.texture();
+        let page = self.ids.iter().zip(&self.layers).position(|(&id, &layer)| id == texture && layer == entry.layer);
+        page.ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not allocated in this atlas", entry.name),
+        ))
+    }
+
+    fn image_name(&self, options: &ExportOptions, index: usize) -> String {
+        options.image_name.replace("{}", &index.to_string())
+    }
+}
+
+/// Write a TexturePacker JSON manifest with the frames in an object keyed by name.
+pub fn write_texture_packer_hash<A: AtlasAllocator>(
+    atlas: &AllocatorList<A>,
+    entries: &[ExportEntry],
+    options: &ExportOptions,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    write_texture_packer(atlas, entries, options, true, output)
+}
+
+/// Write a TexturePacker JSON manifest with the frames in an array.
+pub fn write_texture_packer_array<A: AtlasAllocator>(
+    atlas: &AllocatorList<A>,
+    entries: &[ExportEntry],
+    options: &ExportOptions,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    write_texture_packer(atlas, entries, options, false, output)
+}
+
+fn write_texture_packer<A: AtlasAllocator>(
+    atlas: &AllocatorList<A>,
+    entries: &[ExportEntry],
+    options: &ExportOptions,
+    hash: bool,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let pages = Pages::new(atlas);
+
+    writeln!(output, "{{")?;
+    writeln!(output, "  \"frames\": {}", if hash { "{" } else { "[" })?;
+    for (idx, entry) in entries.iter().enumerate() {
+        let page = pages.index(entry)?;
+        let r = &entry.frame();
+        let (w, h) = (r.width(), r.height());
+        let key = if hash {
+            format!("{}: {{", json_string(entry.name))
+        } else {
+            format!("{{\"filename\": {}, ", json_string(entry.name))
+        };
+        writeln!(
+            output,
+            "    {}\"frame\": {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}}}, \"rotated\": false, \"trimmed\": false, \
+             \"spriteSourceSize\": {{\"x\": 0, \"y\": 0, \"w\": {}, \"h\": {}}}, \"sourceSize\": {{\"w\": {}, \"h\": {}}}, \
+             \"page\": {}, \"texture\": {}, \"layer\": {}, \"image\": {}}}{}",
+            key, r.min.x, r.min.y, w, h, w, h, w, h,
+            page, pages.ids[page].serialize(), pages.layers[page], json_string(&pages.image_name(options, page)),
+            if idx + 1 < entries.len() { "," } else { "" },
+        )?;
+    }
+    writeln!(output, "  {},", if hash { "}" } else { "]" })?;
+
+    writeln!(output, "  \"meta\": {{")?;
+    writeln!(output, "    \"app\": \"texture_atlas\",")?;
+    writeln!(output, "    \"version\": \"1.0\",")?;
+    writeln!(output, "    \"image\": {},", json_string(&pages.image_name(options, 0)))?;
+    writeln!(output, "    \"format\": \"RGBA8888\",")?;
+    let size = pages.first_size(atlas);
+    writeln!(output, "    \"size\": {{\"w\": {}, \"h\": {}}},", size.width, size.height)?;
+    writeln!(output, "    \"scale\": \"1\",")?;
+    writeln!(output, "    \"pages\": [")?;
+    for idx in 0..pages.len() {
+        writeln!(
+            output,
+            "      {{\"page\": {}, \"texture\": {}, \"layer\": {}, \"image\": {}, \"size\": {{\"w\": {}, \"h\": {}}}}}{}",
+            idx, pages.ids[idx].serialize(), pages.layers[idx], json_string(&pages.image_name(options, idx)),
+            pages.sizes[idx].width, pages.sizes[idx].height,
+            if idx + 1 < pages.len() { "," } else { "" },
+        )?;
+    }
+    writeln!(output, "    ]")?;
+    writeln!(output, "  }}")?;
+    writeln!(output, "}}")
+}
+
+/// Write one line per allocation, after a header line.
+pub fn write_csv<A: AtlasAllocator>(
+    atlas: &AllocatorList<A>,
+    entries: &[ExportEntry],
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let pages = Pages::new(atlas);
+
+    writeln!(output, "name,page,texture,layer,x,y,width,height,atlas_width,atlas_height")?;
+    for entry in entries {
+        let page = pages.index(entry)?;
+        let r = &entry.frame();
+        writeln!(
+            output,
+            "{},{},{},{},{},{},{},{},{},{}",
+            csv_field(entry.name), page, pages.ids[page].serialize(), pages.layers[page],
+            r.min.x, r.min.y, r.width(), r.height(),
+            pages.sizes[page].width, pages.sizes[page].height,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write a BMFont text descriptor for a glyph atlas.
+///
+/// Entry names are the glyphs: either a single character, a decimal code point or
+/// `U+` followed by a hexadecimal code point. The advance of each glyph is its width.
+///
+/// The format has a single texture size for all pages, pages of different sizes are
+/// rejected.
+pub fn write_bmfont<A: AtlasAllocator>(
+    atlas: &AllocatorList<A>,
+    entries: &[ExportEntry],
+    options: &ExportOptions,
+    face: &str,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let pages = Pages::new(atlas);
+    let line_height = entries.iter().map(|entry| entry.size.height).max().unwrap_or(0);
+    let size = pages.first_size(atlas);
+    if pages.sizes.iter().any(|&page_size| page_size != size) {
+        return Err(Error::new(ErrorKind::InvalidInput, "BMFont pages must all have the same size"));
+    }
+
+    writeln!(
+        output,
+        "info face={} size={} bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding=0,0,0,0 spacing=0,0",
+        quoted(face), line_height,
+    )?;
+    writeln!(
+        output,
+        "common lineHeight={} base={} scaleW={} scaleH={} pages={} packed=0",
+        line_height, line_height, size.width, size.height, pages.len(),
+    )?;
+    for idx in 0..pages.len() {
+        writeln!(output, "page id={} file={}", idx, quoted(&pages.image_name(options, idx)))?;
+    }
+    writeln!(output, "chars count={}", entries.len())?;
+    for entry in entries {
+        let id = glyph_id(entry.name).ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a character or a code point", entry.name),
+        ))?;
+        let page = pages.index(entry)?;
+        let r = &entry.frame();
+        writeln!(
+            output,
+            "char id={} x={} y={} width={} height={} xoffset=0 yoffset=0 xadvance={} page={} chnl=15",
+            id, r.min.x, r.min.y, r.width(), r.height(), r.width(), page,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn glyph_id(name: &str) -> Option<u32> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(c as u32);
+    }
+    if let Some(hex) = name.strip_prefix("U+") {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+
+    name.parse().ok()
+}
+
+fn json_string(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+
+    result
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "'"))
+}
+
+#[test]
+fn export_formats() {
+    use crate::{ShelfAllocator, size2};
+
+    let mut atlas = AllocatorList::<ShelfAllocator>::new(size2(64, 64), crate::etagere::AllocatorOptions::DEFAULT);
+    let names = ["A", "66", "U+43", "big"];
+    let sizes = [size2(10, 12), size2(8, 12), size2(9, 12), size2(64, 64)];
+    let mut entries = Vec::new();
+    for (name, &size) in names.iter().zip(sizes.iter()) {
+        let alloc = atlas.allocate(size).unwrap();
+        entries.push(ExportEntry { name, handle: alloc.handle, rectangle: alloc.rectangle, size, layer: alloc.layer });
+    }
+
+    let mut csv = Vec::new();
+    write_csv(&atlas, &entries, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 5);
+    assert!(csv.lines().nth(4).unwrap().starts_with("big,1,"));
+    // The shelf allocator rounds heights up, the requested sizes are exported.
+    assert_eq!(entries[0].rectangle.height(), 16);
+    assert!(csv.lines().nth(1).unwrap().starts_with("A,0,1,0,0,0,10,12,"));
+
+    let mut json = Vec::new();
+    write_texture_packer_hash(&atlas, &entries, &ExportOptions::DEFAULT, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("\"U+43\": {\"frame\""));
+    assert!(json.contains("\"A\": {\"frame\": {\"x\": 0, \"y\": 0, \"w\": 10, \"h\": 12}"));
+    assert!(json.contains("\"sourceSize\": {\"w\": 10, \"h\": 12}"));
+    assert!(json.contains("\"image\": \"atlas-1.png\""));
+
+    let mut json = Vec::new();
+    write_texture_packer_array(&atlas, &entries, &ExportOptions::DEFAULT, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("{\"filename\": \"A\""));
+
+    // The last entry isn't a glyph.
+    let mut fnt = Vec::new();
+    assert!(write_bmfont(&atlas, &entries, &ExportOptions::DEFAULT, "test", &mut fnt).is_err());
+
+    let mut fnt = Vec::new();
+    write_bmfont(&atlas, &entries[..3], &ExportOptions::DEFAULT, "test", &mut fnt).unwrap();
+    let fnt = String::from_utf8(fnt).unwrap();
+    assert!(fnt.contains("pages=2"));
+    assert!(fnt.contains("lineHeight=12 "));
+    assert!(fnt.contains("char id=65 x=0 y=0 width=10 height=12 xoffset=0 yoffset=0 xadvance=10 "));
+    for id in &["id=65 ", "id=66 ", "id=67 "] {
+        assert!(fnt.contains(id));
+    }
+}
+
+#[test]
+fn page_sizes() {
+    use crate::{GuillotineAllocator, ShelfAllocator, size2};
+
+    let mut atlas = AllocatorList::<GuillotineAllocator>::new(size2(256, 256), guillotiere::DEFAULT_OPTIONS);
+    atlas.set_min_texture_size(size2(32, 32));
+    let alloc = atlas.allocate(size2(20, 20)).unwrap();
+    let entries = [ExportEntry { name: "a", handle: alloc.handle, rectangle: alloc.rectangle, size: size2(20, 20), layer: 0 }];
+
+    let mut csv = Vec::new();
+    write_csv(&atlas, &entries, &mut csv).unwrap();
+    assert!(String::from_utf8(csv).unwrap().lines().nth(1).unwrap().ends_with(",32,32"));
+
+    let mut json = Vec::new();
+    write_texture_packer_hash(&atlas, &entries, &ExportOptions::DEFAULT, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("\"size\": {\"w\": 32, \"h\": 32},"));
+    assert!(json.contains("\"image\": \"atlas-0.png\", \"size\": {\"w\": 32, \"h\": 32}}"));
+
+    let mut fnt = Vec::new();
+    write_bmfont(&atlas, &entries, &ExportOptions::DEFAULT, "test", &mut fnt).unwrap();
+    assert!(String::from_utf8(fnt).unwrap().contains("scaleW=32 scaleH=32 "));
+
+    // A standalone texture makes a page of a different size.
+    let mut atlas = AllocatorList::<ShelfAllocator>::new(size2(64, 64), crate::etagere::AllocatorOptions::DEFAULT);
+    atlas.set_standalone_textures(Some(crate::StandaloneSize::Exact));
+    let mut entries = Vec::new();
+    for (name, &size) in ["A", "B"].iter().zip([size2(10, 10), size2(100, 20)].iter()) {
+        let alloc = atlas.allocate(size).unwrap();
+        entries.push(ExportEntry { name, handle: alloc.handle, rectangle: alloc.rectangle, size, layer: alloc.layer });
+    }
+
+    let mut csv = Vec::new();
+    write_csv(&atlas, &entries, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.lines().nth(1).unwrap().ends_with(",64,64"));
+    assert!(csv.lines().nth(2).unwrap().ends_with(",100,20"));
+
+    let mut fnt = Vec::new();
+    assert!(write_bmfont(&atlas, &entries, &ExportOptions::DEFAULT, "test", &mut fnt).is_err());
+}
+
+#[test]
+fn layer_pages() {
+    use crate::tiled::{SlotOrder, TileSizes, TiledAllocatorConfig, TiledAllocatorOptions};
+    use crate::{TiledAllocator, size2};
+
+    let config = TiledAllocatorConfig {
+        tile_sizes: TileSizes::WrDefault,
+        layers: vec![
+            TiledAllocatorOptions { region_size: size2(64, 64) },
+            TiledAllocatorOptions { region_size: size2(128, 128) },
+        ],
+        slot_order: SlotOrder::DEFAULT,
+    };
+    let mut atlas = AllocatorList::<TiledAllocator>::new(size2(128, 128), config);
+    let mut entries = Vec::new();
+    for (name, &size) in ["A", "B", "C"].iter().zip([size2(10, 10), size2(100, 100), size2(100, 100)].iter()) {
+        let alloc = atlas.allocate(size).unwrap();
+        entries.push(ExportEntry { name, handle: alloc.handle, rectangle: alloc.rectangle, size, layer: alloc.layer });
+    }
+    // The large tiles are on the second layer of two textures.
+    assert_eq!(entries.iter().map(|entry| entry.layer).collect::<Vec<_>>(), vec![0, 1, 1]);
+    assert_ne!(entries[1].handle.texture(), entries[2].handle.texture());
+
+    let mut csv = Vec::new();
+    write_csv(&atlas, &entries, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.lines().nth(1).unwrap().starts_with("A,0,1,0,"));
+    assert!(csv.lines().nth(2).unwrap().starts_with("B,1,1,1,"));
+    assert!(csv.lines().nth(3).unwrap().starts_with("C,3,2,1,"));
+
+    let mut json = Vec::new();
+    write_texture_packer_hash(&atlas, &entries, &ExportOptions::DEFAULT, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("\"page\": 1, \"texture\": 1, \"layer\": 1, \"image\": \"atlas-1.png\"}"));
+    assert!(json.contains("\"page\": 3, \"texture\": 2, \"layer\": 1, \"image\": \"atlas-3.png\"}"));
+    assert!(json.contains("{\"page\": 2, \"texture\": 2, \"layer\": 0, \"image\": \"atlas-2.png\", "));
+
+    let mut fnt = Vec::new();
+    write_bmfont(&atlas, &entries, &ExportOptions::DEFAULT, "test", &mut fnt).unwrap();
+    let fnt = String::from_utf8(fnt).unwrap();
+    assert!(fnt.contains("pages=4"));
+    assert!(fnt.contains("page id=3 file=\"atlas-3.png\""));
+    assert!(fnt.lines().any(|line| line.starts_with("char id=65 ") && line.contains(" page=0 ")));
+    assert!(fnt.lines().any(|line| line.starts_with("char id=66 ") && line.contains(" page=1 ")));
+    assert!(fnt.lines().any(|line| line.starts_with("char id=67 ") && line.contains(" page=3 ")));
+}
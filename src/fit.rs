@@ -0,0 +1,253 @@
+//! Search for the smallest atlas that can hold a set of rectangles known up front.
+
+use crate::{ArrayAllocation, AtlasAllocator, Rectangle, Size, size2};
+
+/// Constraints on the size of the atlas.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SizeConstraint {
+    /// Any width and height.
+    None,
+    /// The width and height are powers of two.
+    PowerOfTwo,
+    /// The width and height are equal.
+    Square,
+    /// The width and height are the same power of two.
+    SquarePowerOfTwo,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FitOptions {
+    /// Default value: `SizeConstraint::None`.
+    pub constraint: SizeConstraint,
+    /// The largest size to consider.
+    ///
+    /// Default value: 16384x16384.
+    pub max_size: Size,
+}
+
+impl FitOptions {
+    pub const DEFAULT: Self = FitOptions {
+        constraint: SizeConstraint::None,
+        max_size: size2(16384, 16384),
+    };
+}
+
+impl Default for FitOptions {
+    fn default() -> Self {
+        FitOptions::DEFAULT
+    }
+}
+
+/// The smallest atlas found by `find_minimal_size`.
+pub struct Fit<A> {
+    /// The size of the atlas.
+    pub size: Size,
+    /// The atlas containing the allocations.
+    pub atlas: A,
+    /// The allocations, in the order of the requested sizes.
+    pub allocations: Vec<ArrayAllocation>,
+    /// The bounding box of the allocations in all layers. The atlas can be trimmed to
+    /// its maximum.
+    pub bounds: Rectangle,
+}
+
+/// Number of widths tried before refining around the best one.
+const NUM_COARSE_WIDTHS: i32 = 48;
+
+/// Find the atlas with the smallest area that fits all of the provided sizes, using
+/// `AtlasAllocator::allocate_many`.
+///
+/// For each candidate width, the smallest height is found with a binary search. This
+/// assumes that growing the atlas never makes an allocation fail, which holds for
+/// all allocators of this crate in practice.
+///
+/// Returns `None` if the sizes don't fit in `options.max_size`.
+pub fn find_minimal_size<A: AtlasAllocator + Clone>(
+    sizes: &[Size],
+    config: &A::Config,
+    options: &FitOptions,
+) -> Option<Fit<A>> {
+    let min_w = sizes.iter().map(|s| s.width).max().unwrap_or(1).max(1);
+    let min_h = sizes.iter().map(|s| s.height).max().unwrap_or(1).max(1);
+    let total_area: i64 = sizes.iter().map(|s| s.area() as i64).sum();
+    let max_size = options.max_size;
+    if min_w > max_size.width || min_h > max_size.height {
+        return None;
+    }
+
+    let fits = |size: Size| try_fit::<A>(size, sizes, config);
+
+    let best = match options.constraint {
+        SizeConstraint::Square | SizeConstraint::SquarePowerOfTwo => {
+            let pow2 = options.constraint == SizeConstraint::SquarePowerOfTwo;
+            let min_side = min_w.max(min_h).max((total_area as f64).sqrt().ceil() as i32);
+            let max_side = max_size.width.min(max_size.height);
+            smallest(min_side, max_side, pow2, |side| fits(size2(side, side)))
+        }
+        SizeConstraint::None | SizeConstraint::PowerOfTwo => {
+            let pow2 = options.constraint == SizeConstraint::PowerOfTwo;
+            let mut widths = if pow2 {
+                powers_of_two(min_w, max_size.width)
+            } else {
+                geometric_range(min_w, max_size.width, NUM_COARSE_WIDTHS)
+            };
+
+            let mut best: Option<Fit<A>> = None;
+            let search = |widths: &[i32], best: &mut Option<Fit<A>>| {
+                for &w in widths {
+                    let min_h = min_h.max(((total_area + w as i64 - 1) / w as i64) as i32);
+                    if min_h > max_size.height {
+                        continue;
+                    }
+                    if let Some(best) = best {
+                        // Can't beat the current best, even with the smallest height.
+                        if w as i64 * min_h as i64 > best.size.area() as i64 {
+                            continue;
+                        }
+                    }
+                    if let Some(fit) = smallest(min_h, max_size.height, pow2, |h| fits(size2(w, h))) {
+                        let better = match best {
+                            Some(best) => is_better(fit.size, best.size),
+                            None => true,
+                        };
+                        if better {
+                            *best = Some(fit);
+                        }
+                    }
+                }
+            };
+
+            search(&widths, &mut best);
+
+            // Refine between the neighbors of the best coarse width.
+            if let (false, Some(fit)) = (pow2, &best) {
+                let idx = widths.iter().position(|&w| w == fit.size.width).unwrap();
+                let lo = widths[idx.saturating_sub(1)];
+                let hi = widths[(idx + 1).min(widths.len() - 1)];
+                let step = ((hi - lo) / NUM_COARSE_WIDTHS).max(1);
+                widths = (lo..=hi).step_by(step as usize).filter(|&w| w != fit.size.width).collect();
+                search(&widths, &mut best);
+            }
+
+            best
+        }
+    };
+
+    best
+}
+
+/// Find the smallest value in `min..=max` for which `fits` returns something, assuming
+/// that all larger values fit too.
+fn smallest<A>(min: i32, max: i32, pow2: bool, fits: impl Fn(i32) -> Option<Fit<A>>) -> Option<Fit<A>> {
+    let candidates = if pow2 {
+        powers_of_two(min, max)
+    } else {
+        (min..=max).collect()
+    };
+
+    let mut best = fits(*candidates.last()?)?;
+    let (mut lo, mut hi) = (0, candidates.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match fits(candidates[mid]) {
+            Some(fit) => {
+                best = fit;
+                hi = mid;
+            }
+            None => lo = mid + 1,
+        }
+    }
+
+    Some(best)
+}
+
+fn try_fit<A: AtlasAllocator + Clone>(size: Size, sizes: &[Size], config: &A::Config) -> Option<Fit<A>> {
+    let mut atlas = A::new(size, config);
+    let allocations = atlas.allocate_many(sizes).into_iter().collect::<Result<Vec<_>, _>>().ok()?;
+
+    let bounds = allocations
+        .iter()
+        .map(|alloc| alloc.rectangle)
+        .reduce(|a, b| a.union(&b))
+        .unwrap_or_else(Rectangle::zero);
+
+    Some(Fit { size, atlas, allocations, bounds })
+}
+
+fn is_better(a: Size, b: Size) -> bool {
+    (a.area(), a.width.max(a.height)) < (b.area(), b.width.max(b.height))
+}
+
+fn powers_of_two(min: i32, max: i32) -> Vec<i32> {
+    let mut result = Vec::new();
+    let mut value = 1;
+    while value <= max {
+        if value >= min {
+            result.push(value);
+        }
+        value *= 2;
+    }
+
+    result
+}
+
+/// About `count` values from `min` to `max` with a constant ratio between them.
+fn geometric_range(min: i32, max: i32, count: i32) -> Vec<i32> {
+    let ratio = (max as f64 / min as f64).powf(1.0 / count as f64);
+    let mut result = vec![min];
+    let mut value = min as f64;
+    while *result.last().unwrap() < max {
+        value *= ratio;
+        let next = (value.round() as i32).max(result.last().unwrap() + 1).min(max);
+        result.push(next);
+    }
+
+    result
+}
+
+#[test]
+fn minimal_size() {
+    use crate::{GuillotineAllocator, ShelfAllocator};
+
+    let mut rng = crate::TestRng::new(5);
+    let sizes: Vec<Size> = (0..100).map(|_| size2(rng.range(4, 40), rng.range(4, 40))).collect();
+    let total_area: i32 = sizes.iter().map(|s| s.area()).sum();
+
+    for &constraint in &[
+        SizeConstraint::None,
+        SizeConstraint::PowerOfTwo,
+        SizeConstraint::Square,
+        SizeConstraint::SquarePowerOfTwo,
+    ] {
+        let options = FitOptions { constraint, .. FitOptions::DEFAULT };
+        let fit = find_minimal_size::<GuillotineAllocator>(&sizes, &guillotiere::DEFAULT_OPTIONS, &options).unwrap();
+        let size = fit.size;
+
+        assert!(size.area() >= total_area);
+        assert_eq!(fit.allocations.len(), sizes.len());
+        match constraint {
+            SizeConstraint::PowerOfTwo => assert!((size.width as u32).is_power_of_two() && (size.height as u32).is_power_of_two()),
+            SizeConstraint::Square => assert_eq!(size.width, size.height),
+            SizeConstraint::SquarePowerOfTwo => assert!(size.width == size.height && (size.width as u32).is_power_of_two()),
+            SizeConstraint::None => {
+                // Not much larger than a square fit.
+                let square = find_minimal_size::<GuillotineAllocator>(&sizes, &guillotiere::DEFAULT_OPTIONS, &FitOptions {
+                    constraint: SizeConstraint::Square,
+                    .. FitOptions::DEFAULT
+                }).unwrap();
+                assert!(size.area() <= square.size.area());
+            }
+        }
+
+        let mut oracle = crate::OccupancyOracle::new(size, 1);
+        for (alloc, requested) in fit.allocations.iter().zip(sizes.iter()) {
+            let rect = &alloc.rectangle;
+            assert!(rect.width() >= requested.width && rect.height() >= requested.height);
+            assert!(fit.bounds.contains_box(rect));
+            oracle.add(alloc.layer, rect);
+        }
+    }
+
+    let options = FitOptions { max_size: size2(64, 64), .. FitOptions::DEFAULT };
+    assert!(find_minimal_size::<ShelfAllocator>(&sizes, &crate::etagere::AllocatorOptions::DEFAULT, &options).is_none());
+}
@@ -1,6 +1,7 @@
 use std::default::Default;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FreeListHandle(pub(crate) u32);
 
 impl FreeListHandle {
@@ -17,11 +18,15 @@ impl FreeListHandle {
     pub fn deserialize(bytes: u32) -> Self { FreeListHandle(bytes) }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FreeListItem<T> {
     item: T,
     next: FreeListHandle,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FreeList<T> {
     items: Vec<FreeListItem<T>>,
     first_free: FreeListHandle,
@@ -10,6 +10,14 @@ pub mod free_list;
 pub mod etagere;
 //pub mod array;
 pub mod tiled;
+pub mod svg;
+pub mod raster;
+pub mod replay;
+pub mod export;
+pub mod fit;
+pub mod transient;
+pub mod dispatch;
+mod batch;
 
 pub use euclid::{vec2, point2, size2};
 
@@ -19,7 +27,12 @@ pub type Rectangle = euclid::default::Box2D<i32>;
 
 pub type GuillotineAllocator = guillotiere::AtlasAllocator;
 pub type ShelfAllocator = etagere::AtlasAllocator;
+pub type WideShelfAllocator = etagere::WideShelfAllocator;
 pub use crate::tiled::TiledAllocator;
+pub use crate::transient::TransientAllocator;
+pub use crate::dispatch::{AllocatorDispatcher, DispatchHandle, DynAllocatorList};
+pub use crate::svg::{SvgOptions, SvgAllocation};
+pub use crate::raster::PixelFormat;
 
 
 
@@ -29,18 +42,58 @@ pub use crate::tiled::TiledAllocator;
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AllocId(pub(crate) u32);
 
+/// An allocated rectangle in one of the layers of an atlas.
+///
+/// Allocators that have a single layer always return layer zero.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArrayAllocation {
+    pub id: AllocId,
+    pub layer: u16,
+    pub rectangle: Rectangle,
+}
+
 /// ID of a texture.
 #[repr(C)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TextureId(pub(crate) u32);
 
+impl TextureId {
+    pub fn serialize(&self) -> u32 {
+        self.0
+    }
+
+    pub fn deserialize(bytes: u32) -> Self {
+        TextureId(bytes)
+    }
+}
+
+/// An allocated rectangle in one of the textures of an `AllocatorList`.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Allocation {
-    pub id: AllocId,
-    pub texture: TextureId,
+    pub handle: Handle,
+    pub layer: u16,
     pub rectangle: Rectangle,
+    /// The size of the texture at the time of the allocation.
+    pub texture_size: Size,
+}
+
+impl Allocation {
+    fn new(texture: TextureId, texture_size: Size, alloc: ArrayAllocation) -> Self {
+        Allocation {
+            handle: Handle { texture, alloc: alloc.id },
+            layer: alloc.layer,
+            rectangle: alloc.rectangle,
+            texture_size,
+        }
+    }
+
+    /// The texture that contains the allocation.
+    pub fn texture(&self) -> TextureId {
+        self.handle.texture
+    }
 }
 
 #[repr(C)]
@@ -51,7 +104,15 @@ pub struct Handle {
     alloc: AllocId,
 }
 
+impl Handle {
+    /// The texture that contains the allocation.
+    pub fn texture(&self) -> TextureId {
+        self.texture
+    }
+}
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct TextureIdGenerator {
     next: u32,
 }
@@ -71,13 +132,22 @@ impl TextureIdGenerator {
     }
 }
 
+/// The reason why a rectangle could not be allocated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AllocError {
+    /// The rectangle is larger than the atlas.
+    TooLarge,
+    /// There isn't enough free space for the rectangle.
+    OutOfSpace,
+}
+
 pub trait AtlasAllocator {
     type Config;
 
     fn new(size: Size, config: &Self::Config) -> Self;
 
     /// Allocate a rectangle in the atlas.
-    fn allocate(&mut self, size: Size) -> Option<(AllocId, Rectangle)>;
+    fn allocate(&mut self, size: Size) -> Option<ArrayAllocation>;
 
     /// Deallocate a rectangle in the atlas.
     fn deallocate(&mut self, id: AllocId);
@@ -85,11 +155,85 @@ pub trait AtlasAllocator {
     /// The total size of the atlas.
     fn size(&self) -> Size;
 
+    /// Resize the atlas without moving the allocations. The new size can't be smaller
+    /// than the current one in either dimension.
+    ///
+    /// Returns false without changing anything if the allocator can't grow.
+    fn grow(&mut self, _new_size: Size) -> bool {
+        false
+    }
+
     fn clear(&mut self);
 
     fn is_empty(&self) -> bool;
 
+    /// Number of layers of the atlas, each of them of the size of the atlas.
+    fn num_layers(&self) -> u16 {
+        1
+    }
+
+    /// The area of the atlas that is used by allocations, summed over all layers.
+    fn allocated_space(&self) -> i32;
+
+    /// Invoke a callback with the layer and rectangle of each allocation.
+    ///
+    /// Allocators that don't keep track of individual items may merge several
+    /// allocations into a single rectangle.
+    fn for_each_allocated_rectangle(&self, callback: &mut dyn FnMut(u16, &Rectangle));
+
+    /// Allocate a set of rectangles that is known up front.
+    ///
+    /// Several orderings of the set are tried and the one that fails the fewest
+    /// allocations and uses the least area is kept. The results are in the same order
+    /// as `sizes`.
+    ///
+    /// The atlas is restored from a copy between orderings.
+    fn allocate_many(&mut self, sizes: &[Size]) -> Vec<Result<ArrayAllocation, AllocError>>
+    where
+        Self: Sized + Clone,
+    {
+        batch::allocate_batch(
+            self,
+            sizes,
+            |atlas, size| {
+                if size.width > atlas.size().width || size.height > atlas.size().height {
+                    return Err(AllocError::TooLarge);
+                }
+                atlas.allocate(size).ok_or(AllocError::OutOfSpace)
+            },
+            Self::clone,
+            |atlas, snapshot| *atlas = snapshot.clone(),
+            |alloc| ((0, alloc.layer), alloc.rectangle),
+        )
+    }
+
+    /// Render the occupancy of the first layer of the atlas into a tightly packed image
+    /// of the size of the atlas.
+    ///
+    /// Each allocated rectangle gets a distinct color and the free space is gray.
+    fn rasterize(&self, format: PixelFormat, pixels: &mut [u8]) {
+        self.rasterize_layer(0, format, pixels);
+    }
+
+    /// Same as `rasterize` for the provided layer.
+    fn rasterize_layer(&self, layer: u16, format: PixelFormat, pixels: &mut [u8]) {
+        let mut rectangles = Vec::new();
+        self.for_each_allocated_rectangle(&mut |l, rect| if l == layer {
+            rectangles.push(*rect);
+        });
+        raster::rasterize_rectangles(self.size(), &mut rectangles.into_iter(), format, pixels);
+    }
+
     fn dump_into_svg(&self, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()>;
+
+    /// Same as `dump_into_svg`, with the provided allocations drawn on top of the atlas.
+    fn dump_into_svg_with_options(
+        &self,
+        rect: Option<&Rectangle>,
+        options: &SvgOptions,
+        allocations: &[SvgAllocation],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()>;
 }
 
 impl AtlasAllocator for guillotiere::AtlasAllocator {
@@ -99,11 +243,12 @@ impl AtlasAllocator for guillotiere::AtlasAllocator {
         guillotiere::AtlasAllocator::with_options(size, options)
     }
 
-    fn allocate(&mut self, size: Size) -> Option<(AllocId, Rectangle)> {
-        self.allocate(size).map(|allocation| (
-            AllocId(allocation.id.serialize()),
-            allocation.rectangle,
-        ))
+    fn allocate(&mut self, size: Size) -> Option<ArrayAllocation> {
+        self.allocate(size).map(|allocation| ArrayAllocation {
+            id: AllocId(allocation.id.serialize()),
+            layer: 0,
+            rectangle: allocation.rectangle,
+        })
     }
 
     fn deallocate(&mut self, id: AllocId) {
@@ -114,6 +259,11 @@ impl AtlasAllocator for guillotiere::AtlasAllocator {
         self.size()
     }
 
+    fn grow(&mut self, new_size: Size) -> bool {
+        self.grow(new_size);
+        true
+    }
+
     fn clear(&mut self) {
         self.clear();
     }
@@ -122,9 +272,45 @@ impl AtlasAllocator for guillotiere::AtlasAllocator {
         self.is_empty()
     }
 
+    fn allocated_space(&self) -> i32 {
+        let mut space = 0;
+        self.for_each_allocated_rectangle(|_, rect| space += rect.area());
+
+        space
+    }
+
+    fn for_each_allocated_rectangle(&self, callback: &mut dyn FnMut(u16, &Rectangle)) {
+        self.for_each_allocated_rectangle(|_, rect| callback(0, rect));
+    }
+
     fn dump_into_svg(&self, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
         guillotiere::dump_into_svg(self, rect, output)
     }
+
+    fn dump_into_svg_with_options(
+        &self,
+        rect: Option<&Rectangle>,
+        options: &SvgOptions,
+        allocations: &[SvgAllocation],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        guillotiere::dump_into_svg(self, rect, output)?;
+
+        if options.coloring == svg::Coloring::AllocatorKind {
+            // guillotiere's dump has its own colors, paint over them.
+            let mut all_allocations = Vec::new();
+            self.for_each_allocated_rectangle(|_, rectangle| all_allocations.push(SvgAllocation {
+                rectangle: *rectangle,
+                layer: 0,
+                label: None,
+                age: 0,
+            }));
+            let options = SvgOptions { labels: false, .. *options };
+            svg::dump_allocations_into_svg(self.size(), rect, svg::AllocatorKind::Guillotine, &options, &all_allocations, output)?;
+        }
+
+        svg::dump_allocations_into_svg(self.size(), rect, svg::AllocatorKind::Guillotine, options, allocations, output)
+    }
 }
 
 impl AtlasAllocator for etagere::AtlasAllocator {
@@ -134,11 +320,12 @@ impl AtlasAllocator for etagere::AtlasAllocator {
         etagere::AtlasAllocator::with_options(size, options)
     }
 
-    fn allocate(&mut self, size: Size) -> Option<(AllocId, Rectangle)> {
-        self.allocate(size).map(|(alloc, rect)| (
-            AllocId(alloc.serialize()),
-            rect,
-        ))
+    fn allocate(&mut self, size: Size) -> Option<ArrayAllocation> {
+        self.allocate(size).map(|(alloc, rectangle)| ArrayAllocation {
+            id: AllocId(alloc.serialize()),
+            layer: 0,
+            rectangle,
+        })
     }
 
     fn deallocate(&mut self, id: AllocId) {
@@ -157,24 +344,92 @@ impl AtlasAllocator for etagere::AtlasAllocator {
         self.is_empty()
     }
 
+    fn allocated_space(&self) -> i32 {
+        self.allocated_space()
+    }
+
+    fn for_each_allocated_rectangle(&self, callback: &mut dyn FnMut(u16, &Rectangle)) {
+        self.for_each_allocated_rectangle(|rect| callback(0, rect));
+    }
+
     fn dump_into_svg(&self, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
         etagere::dump_into_svg(self, rect, output)
     }
+
+    fn dump_into_svg_with_options(
+        &self,
+        rect: Option<&Rectangle>,
+        options: &SvgOptions,
+        allocations: &[SvgAllocation],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        etagere::dump_into_svg_with_options(self, rect, options, allocations, output)
+    }
+}
+
+impl AtlasAllocator for etagere::WideShelfAllocator {
+    type Config = etagere::AllocatorOptions;
+
+    fn new(size: Size, options: &etagere::AllocatorOptions) -> Self {
+        etagere::WideShelfAllocator::with_options(size, options)
+    }
+
+    fn allocate(&mut self, size: Size) -> Option<ArrayAllocation> {
+        self.allocate(size).map(|(handle, rectangle)| ArrayAllocation {
+            id: AllocId(handle.serialize()),
+            layer: 0,
+            rectangle,
+        })
+    }
+
+    fn deallocate(&mut self, id: AllocId) {
+        self.deallocate(free_list::FreeListHandle::deserialize(id.0));
+    }
+
+    fn size(&self) -> Size {
+        self.atlas().size()
+    }
+
+    fn clear(&mut self) {
+        self.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.atlas().is_empty()
+    }
+
+    fn allocated_space(&self) -> i32 {
+        self.atlas().allocated_space()
+    }
+
+    fn for_each_allocated_rectangle(&self, callback: &mut dyn FnMut(u16, &Rectangle)) {
+        self.atlas().for_each_allocated_rectangle(|rect| callback(0, rect));
+    }
+
+    fn dump_into_svg(&self, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        etagere::dump_into_svg(self.atlas(), rect, output)
+    }
+
+    fn dump_into_svg_with_options(
+        &self,
+        rect: Option<&Rectangle>,
+        options: &SvgOptions,
+        allocations: &[SvgAllocation],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        etagere::dump_into_svg_with_options(self.atlas(), rect, options, allocations, output)
+    }
 }
 
 impl AtlasAllocator for crate::tiled::TiledAllocator {
-    type Config = (crate::tiled::TileSizes, Size);
+    type Config = crate::tiled::TiledAllocatorConfig;
 
-    fn new(size: Size, options: &(crate::tiled::TileSizes, Size)) -> Self {
-        crate::tiled::TiledAllocator::new(
-            size,
-            options.0,
-            &[crate::tiled::TiledAllocatorOptions { region_size: options.1 }],
-        )
+    fn new(size: Size, config: &crate::tiled::TiledAllocatorConfig) -> Self {
+        crate::tiled::TiledAllocator::with_slot_order(size, config.tile_sizes, &config.layers, config.slot_order)
     }
 
-    fn allocate(&mut self, size: Size) -> Option<(AllocId, Rectangle)> {
-        self.allocate(size).map(|alloc| (alloc.id, alloc.rectangle))
+    fn allocate(&mut self, size: Size) -> Option<ArrayAllocation> {
+        self.allocate(size)
     }
 
     fn deallocate(&mut self, id: AllocId) {
@@ -193,15 +448,122 @@ impl AtlasAllocator for crate::tiled::TiledAllocator {
         self.is_empty()
     }
 
+    fn num_layers(&self) -> u16 {
+        self.num_layers()
+    }
+
+    fn allocated_space(&self) -> i32 {
+        self.allocated_space()
+    }
+
+    fn for_each_allocated_rectangle(&self, callback: &mut dyn FnMut(u16, &Rectangle)) {
+        self.for_each_allocated_rectangle(|layer, rect| callback(layer, rect));
+    }
+
     fn dump_into_svg(&self, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
         tiled::dump_into_svg(self, rect, output)
     }
+
+    fn dump_into_svg_with_options(
+        &self,
+        rect: Option<&Rectangle>,
+        options: &SvgOptions,
+        allocations: &[SvgAllocation],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        tiled::dump_into_svg_with_options(self, rect, options, allocations, output)
+    }
+}
+
+impl AtlasAllocator for crate::transient::TransientAllocator {
+    type Config = crate::transient::TransientAllocatorOptions;
+
+    fn new(size: Size, options: &crate::transient::TransientAllocatorOptions) -> Self {
+        crate::transient::TransientAllocator::with_options(size, options)
+    }
+
+    fn allocate(&mut self, size: Size) -> Option<ArrayAllocation> {
+        self.allocate(size).map(|(id, rectangle)| ArrayAllocation { id, layer: 0, rectangle })
+    }
+
+    fn deallocate(&mut self, id: AllocId) {
+        self.deallocate(id);
+    }
+
+    fn size(&self) -> Size {
+        self.size()
+    }
+
+    fn grow(&mut self, new_size: Size) -> bool {
+        self.grow(new_size);
+        true
+    }
+
+    fn clear(&mut self) {
+        self.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.is_empty()
+    }
+
+    fn allocated_space(&self) -> i32 {
+        self.allocated_space()
+    }
+
+    fn for_each_allocated_rectangle(&self, callback: &mut dyn FnMut(u16, &Rectangle)) {
+        self.for_each_allocated_rectangle(|rect| callback(0, rect));
+    }
+
+    fn dump_into_svg(&self, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        transient::dump_into_svg(self, rect, output)
+    }
+
+    fn dump_into_svg_with_options(
+        &self,
+        rect: Option<&Rectangle>,
+        options: &SvgOptions,
+        allocations: &[SvgAllocation],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        transient::dump_into_svg_with_options(self, rect, options, allocations, output)
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct Texture<Allocator> {
     id: TextureId,
     allocator: Allocator,
+    /// The frame at which the texture became empty, if it is empty and waiting to be
+    /// released.
+    #[cfg_attr(feature = "serde", serde(default))]
+    empty_since: Option<u64>,
+    /// Set for the dedicated texture of an oversized request.
+    #[cfg_attr(feature = "serde", serde(default))]
+    standalone: bool,
+}
+
+/// A change in the set of textures of an `AllocatorList`, see `AllocatorList::drain_events`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureEvent {
+    /// A texture of the provided size must be created before the allocations in it are used.
+    TextureCreated(TextureId, Size),
+    /// The texture was resized and its content must be copied into a texture of the new
+    /// size. The allocations keep their position.
+    TextureGrown(TextureId, Size),
+    /// The texture doesn't contain allocations anymore and can be destroyed.
+    TextureReleased(TextureId),
+}
+
+impl TextureEvent {
+    pub fn texture(&self) -> TextureId {
+        match *self {
+            TextureEvent::TextureCreated(id, _)
+            | TextureEvent::TextureGrown(id, _)
+            | TextureEvent::TextureReleased(id) => id,
+        }
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -210,6 +572,53 @@ pub struct AllocatorList<Allocator: AtlasAllocator> {
     size: Size,
     ids: TextureIdGenerator,
     config: Allocator::Config,
+    /// Set when the textures are the layers of a texture array.
+    #[cfg_attr(feature = "serde", serde(default))]
+    array: Option<TextureArray>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    events: Vec<TextureEvent>,
+    /// Number of frames during which an empty texture is kept before being released.
+    #[cfg_attr(feature = "serde", serde(default))]
+    release_delay: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    frame: u64,
+    /// The size of new textures, if they start small and grow up to `size`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    min_texture_size: Option<Size>,
+    /// Set when requests larger than `size` get a texture of their own.
+    #[cfg_attr(feature = "serde", serde(default))]
+    standalone: Option<StandaloneSize>,
+}
+
+/// The size of the texture that is created for a request that is larger than the
+/// textures of an `AllocatorList`, see `AllocatorList::set_standalone_textures`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StandaloneSize {
+    /// The size of the request.
+    Exact,
+    /// The size of the request, with each side rounded up to a power of two.
+    PowerOfTwo,
+}
+
+/// The state of an `AllocatorList` created with `with_texture_array`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct TextureArray {
+    /// Number of array layers used by each texture, which is the number of layers of its
+    /// allocator.
+    layers_per_texture: u16,
+    /// The number of layers last reported by `array_resize`.
+    reported_layers: u32,
+}
+
+/// A change in the number of layers of the texture array of an `AllocatorList`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ArrayResize {
+    /// The array must be grown to this number of layers before the new allocations are used.
+    Grow(u32),
+    /// The layers after this number are unused and the array can be shrunk.
+    Shrink(u32),
 }
 
 impl<Allocator: AtlasAllocator> AllocatorList<Allocator> {
@@ -219,33 +628,271 @@ impl<Allocator: AtlasAllocator> AllocatorList<Allocator> {
             size,
             config,
             ids: TextureIdGenerator::new(),
+            array: None,
+            events: Vec::new(),
+            release_delay: 0,
+            frame: 0,
+            min_texture_size: None,
+            standalone: None,
         }
     }
 
-    pub fn allocate(&mut self, size: Size) -> Option<(Handle, Rectangle)> {
-        if size.width > self.size.width || size.height > self.size.height {
+    /// Start new textures at the provided size instead of the size of the list, and grow
+    /// them in doubling steps up to the size of the list when they are full.
+    ///
+    /// Allocators that can't grow get a new texture instead, one step larger than the
+    /// largest existing texture.
+    ///
+    /// Texture arrays need all of their layers to have the same size and don't support this.
+    pub fn set_min_texture_size(&mut self, size: Size) {
+        assert!(self.array.is_none(), "texture arrays can't grow");
+        assert!(size.width > 0 && size.height > 0, "the minimum texture size must be positive");
+        self.min_texture_size = Some(size.min(self.size));
+    }
+
+    /// Give requests that are larger than the size of the list a dedicated texture instead
+    /// of failing. The texture only holds that allocation and is released as soon as it
+    /// is deallocated, regardless of the release delay.
+    ///
+    /// This needs an allocator that can place a rectangle of the size of its atlas. The
+    /// `TiledAllocator` can't: its regions and tile sizes are fixed by its configuration
+    /// and tile sizes stop at 512 pixels, so requests larger than the list still fail
+    /// with it.
+    ///
+    /// Texture arrays need all of their layers to have the same size and don't support this.
+    pub fn set_standalone_textures(&mut self, size: Option<StandaloneSize>) {
+        assert!(size.is_none() || self.array.is_none(), "texture arrays can't have standalone textures");
+        self.standalone = size;
+    }
+
+    /// Whether the texture was created for a single oversized request.
+    pub fn is_standalone_texture(&self, id: TextureId) -> bool {
+        self.textures.iter().any(|texture| texture.id == id && texture.standalone)
+    }
+
+    /// The current size of a texture.
+    pub fn texture_size(&self, id: TextureId) -> Option<Size> {
+        self.textures.iter().find(|texture| texture.id == id).map(|texture| texture.allocator.size())
+    }
+
+    /// The number of layers of a texture.
+    pub fn texture_layers(&self, id: TextureId) -> Option<u16> {
+        self.textures.iter().find(|texture| texture.id == id).map(|texture| texture.allocator.num_layers())
+    }
+
+    /// The next size in the doubling sequence, or `None` if the maximum size is reached.
+    fn next_texture_size(&self, size: Size) -> Option<Size> {
+        if size == self.size {
             return None;
         }
 
+        Some((size * 2).min(self.size))
+    }
+
+    /// Keep empty textures during the provided number of calls to `end_frame` before
+    /// releasing them, so that they can be reused if allocations come back in the meantime.
+    ///
+    /// With the default delay of zero, textures are released as soon as they are empty.
+    pub fn set_release_delay(&mut self, frames: u32) {
+        self.release_delay = frames;
+    }
+
+    /// Release the textures that have been empty for longer than the release delay.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+
+        let frame = self.frame;
+        let delay = self.release_delay as u64;
+        let events = &mut self.events;
+        self.textures.retain(|texture| match texture.empty_since {
+            Some(since) if frame - since >= delay => {
+                events.push(TextureEvent::TextureReleased(texture.id));
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// Take the texture creation and release events that happened since the previous call.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, TextureEvent> {
+        self.events.drain(..)
+    }
+
+    /// Create a list whose textures are the layers of a single texture array.
+    ///
+    /// The id of each texture is its slot in the array: new textures take the lowest free
+    /// slot and the slots of released textures are reused. Allocators with several layers
+    /// occupy that many consecutive array layers, see `array_layer`.
+    pub fn with_texture_array(size: Size, config: Allocator::Config) -> Self {
+        AllocatorList {
+            array: Some(TextureArray { layers_per_texture: 1, reported_layers: 0 }),
+            .. AllocatorList::new(size, config)
+        }
+    }
+
+    /// Whether the list was created with `with_texture_array`.
+    pub fn is_texture_array(&self) -> bool {
+        self.array.is_some()
+    }
+
+    /// The index in the texture array of a layer of a texture.
+    ///
+    /// Only meaningful for lists created with `with_texture_array`.
+    pub fn array_layer(&self, texture: TextureId, layer: u16) -> u32 {
+        let layers_per_texture = self.array.map(|array| array.layers_per_texture).unwrap_or(1);
+
+        texture.0 * layers_per_texture as u32 + layer as u32
+    }
+
+    /// The number of layers the texture array needs to hold all textures.
+    pub fn required_array_layers(&self) -> u32 {
+        match (self.array, self.textures.last()) {
+            // Textures are sorted by slot.
+            (Some(array), Some(last)) => (last.id.0 + 1) * array.layers_per_texture as u32,
+            _ => 0,
+        }
+    }
+
+    /// Report whether the texture array must grow or can shrink since the previous call,
+    /// and consider the change applied.
+    ///
+    /// Must be called after allocating and before using the new allocations.
+    pub fn array_resize(&mut self) -> Option<ArrayResize> {
+        let required = self.required_array_layers();
+        let array = self.array.as_mut()?;
+        let previous = array.reported_layers;
+        array.reported_layers = required;
+
+        match required.cmp(&previous) {
+            std::cmp::Ordering::Greater => Some(ArrayResize::Grow(required)),
+            std::cmp::Ordering::Less => Some(ArrayResize::Shrink(required)),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    fn add_texture(&mut self, size: Size) -> &mut Texture<Allocator> {
+        let allocator = Allocator::new(size, &self.config);
+        let idx = match self.array.as_mut() {
+            Some(array) => {
+                array.layers_per_texture = allocator.num_layers();
+
+                // The lowest slot that isn't used by a texture.
+                let idx = self.textures
+                    .iter()
+                    .enumerate()
+                    .position(|(slot, texture)| texture.id.0 != slot as u32)
+                    .unwrap_or(self.textures.len());
+                self.textures.insert(idx, Texture { id: TextureId(idx as u32), allocator, empty_since: None, standalone: false });
+
+                idx
+            }
+            None => {
+                self.textures.push(Texture { id: self.ids.generate(), allocator, empty_since: None, standalone: false });
+
+                self.textures.len() - 1
+            }
+        };
+
+        let texture = &mut self.textures[idx];
+        self.events.push(TextureEvent::TextureCreated(texture.id, size));
+
+        texture
+    }
+
+    pub fn allocate(&mut self, size: Size) -> Option<Allocation> {
+        if size.width > self.size.width || size.height > self.size.height {
+            return self.allocate_standalone(size);
+        }
+
         for texture in &mut self.textures {
-            if let Some((alloc, rectangle)) = texture.allocator.allocate(size) {
-                return Some((
-                    Handle { texture: texture.id, alloc },
-                    rectangle
-                ));
+            if texture.standalone {
+                continue;
+            }
+            if let Some(alloc) = texture.allocator.allocate(size) {
+                texture.empty_since = None;
+                return Some(Allocation::new(texture.id, texture.allocator.size(), alloc));
             }
         }
 
-        self.textures.push(Texture {
-            id: self.ids.generate(),
-            allocator: Allocator::new(self.size, &self.config),
-        });
+        let min_size = match self.min_texture_size {
+            Some(min_size) => min_size,
+            None => {
+                let texture = self.add_texture(self.size);
+                return texture.allocator.allocate(size).map(|alloc| Allocation::new(texture.id, texture.allocator.size(), alloc));
+            }
+        };
 
-        let texture = self.textures.last_mut().unwrap();
-        texture.allocator.allocate(size).map(|(alloc, rectangle)| (
-            Handle { texture: texture.id, alloc },
-            rectangle,
-        ))
+        for idx in 0..self.textures.len() {
+            if self.textures[idx].standalone {
+                continue;
+            }
+            if let Some(alloc) = self.grow_and_allocate(idx, size) {
+                return Some(alloc);
+            }
+        }
+
+        // One step larger than the largest texture, and large enough for the request.
+        let largest = self.textures.iter()
+            .filter(|texture| !texture.standalone)
+            .map(|texture| texture.allocator.size())
+            .reduce(Size::max);
+        let mut texture_size = match largest {
+            Some(largest) => self.next_texture_size(largest).unwrap_or(largest),
+            None => min_size,
+        };
+        while texture_size.width < size.width || texture_size.height < size.height {
+            texture_size = self.next_texture_size(texture_size).unwrap();
+        }
+
+        let texture = self.add_texture(texture_size);
+        let texture_id = texture.id;
+        if let Some(alloc) = texture.allocator.allocate(size) {
+            return Some(Allocation::new(texture_id, texture_size, alloc));
+        }
+
+        let idx = self.textures.iter().position(|texture| texture.id == texture_id).unwrap();
+        self.grow_and_allocate(idx, size)
+    }
+
+    /// Allocate an oversized request in a texture of its own, if standalone textures
+    /// are enabled.
+    ///
+    /// Fails if a side rounded up to a power of two doesn't fit in an `i32`.
+    fn allocate_standalone(&mut self, size: Size) -> Option<Allocation> {
+        let power_of_two = |side: i32| {
+            (side as u32).checked_next_power_of_two().filter(|&side| side <= i32::MAX as u32).map(|side| side as i32)
+        };
+        let texture_size = match self.standalone? {
+            StandaloneSize::Exact => size,
+            StandaloneSize::PowerOfTwo => size2(power_of_two(size.width)?, power_of_two(size.height)?),
+        };
+
+        let mut allocator = Allocator::new(texture_size, &self.config);
+        let alloc = allocator.allocate(size)?;
+
+        let id = self.ids.generate();
+        self.textures.push(Texture { id, allocator, empty_since: None, standalone: true });
+        self.events.push(TextureEvent::TextureCreated(id, texture_size));
+
+        Some(Allocation::new(id, texture_size, alloc))
+    }
+
+    /// Grow a texture in doubling steps until the allocation succeeds or the texture
+    /// can't grow anymore.
+    fn grow_and_allocate(&mut self, idx: usize, size: Size) -> Option<Allocation> {
+        loop {
+            let new_size = self.next_texture_size(self.textures[idx].allocator.size())?;
+            let texture = &mut self.textures[idx];
+            if !texture.allocator.grow(new_size) {
+                return None;
+            }
+            self.events.push(TextureEvent::TextureGrown(texture.id, new_size));
+
+            if let Some(alloc) = texture.allocator.allocate(size) {
+                texture.empty_since = None;
+                return Some(Allocation::new(texture.id, new_size, alloc));
+            }
+        }
     }
 
     pub fn deallocate(&mut self, handle: Handle) {
@@ -257,33 +904,151 @@ impl<Allocator: AtlasAllocator> AllocatorList<Allocator> {
             texture.allocator.deallocate(handle.alloc);
 
             if texture.allocator.is_empty() {
-                empty_index = Some(idx)
+                if self.release_delay > 0 && !texture.standalone {
+                    texture.empty_since = Some(self.frame);
+                } else {
+                    empty_index = Some(idx)
+                }
             }
 
             break;
         }
 
         if let Some(idx) = empty_index {
-            self.textures.swap_remove(idx);
+            self.events.push(TextureEvent::TextureReleased(self.textures[idx].id));
+            if self.array.is_some() {
+                // Keep the textures sorted by slot.
+                self.textures.remove(idx);
+            } else {
+                self.textures.swap_remove(idx);
+            }
         }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.textures.is_empty()
+        self.textures.iter().all(|texture| texture.allocator.is_empty())
     }
 
+    /// Number of textures, including the empty ones that wait to be released.
     pub fn num_textures(&self) -> usize {
         self.textures.len()
     }
 
+    /// The ids of the textures that currently exist, in no particular order, or by slot
+    /// for texture arrays.
+    pub fn texture_ids(&self) -> Vec<TextureId> {
+        self.textures.iter().map(|texture| texture.id).collect()
+    }
+
+    /// Total allocated area of all textures.
+    pub fn allocated_space(&self) -> i64 {
+        self.textures.iter().map(|texture| texture.allocator.allocated_space() as i64).sum()
+    }
+
+    /// The size of each texture, or the maximum size of the textures if they grow.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The configuration used to create each texture's allocator.
+    pub fn config(&self) -> &Allocator::Config {
+        &self.config
+    }
+
+    /// Allocate a set of rectangles that is known up front.
+    ///
+    /// Several orderings of the set are tried and the one that needs the fewest textures
+    /// and the least area is kept. The results are in the same order as `sizes`.
+    ///
+    /// The list is restored from a copy of its textures between orderings, so that the
+    /// textures created or grown by the rejected orderings leave no trace.
+    pub fn allocate_many(&mut self, sizes: &[Size]) -> Vec<Result<Allocation, AllocError>>
+    where
+        Allocator: Clone,
+    {
+        batch::allocate_batch(
+            self,
+            sizes,
+            |atlas, size| {
+                let too_large = size.width > atlas.size.width || size.height > atlas.size.height;
+                if too_large && atlas.standalone.is_none() {
+                    return Err(AllocError::TooLarge);
+                }
+                atlas.allocate(size).ok_or(AllocError::OutOfSpace)
+            },
+            |atlas| (atlas.textures.clone(), atlas.ids.clone(), atlas.events.clone()),
+            |atlas, (textures, ids, events)| {
+                atlas.textures = textures.clone();
+                atlas.ids = ids.clone();
+                atlas.events = events.clone();
+            },
+            |alloc| ((alloc.handle.texture.0, alloc.layer), alloc.rectangle),
+        )
+    }
+
+    /// Render the occupancy of all layers of all textures into a single image, from left
+    /// to right. Each texture takes the space of the largest texture, smaller textures
+    /// are drawn in the top-left corner of their slot.
+    ///
+    /// Returns the size of the image and its tightly packed pixels.
+    pub fn rasterize(&self, format: PixelFormat) -> (Size, Vec<u8>) {
+        let spacing = 4;
+        let layers: Vec<(&Allocator, u16)> = self.textures.iter()
+            .flat_map(|texture| (0..texture.allocator.num_layers()).map(move |layer| (&texture.allocator, layer)))
+            .collect();
+        let slot = layers.iter().fold(self.size, |slot, (allocator, _)| slot.max(allocator.size()));
+        let n = layers.len() as i32;
+        let size = size2(
+            (slot.width * n + spacing * (n - 1)).max(0),
+            if n > 0 { slot.height } else { 0 },
+        );
+
+        let bpp = format.bytes_per_pixel();
+        let mut pixels = vec![0; size.area() as usize * bpp];
+        let mut texture_pixels = Vec::new();
+
+        for (idx, &(allocator, layer)) in layers.iter().enumerate() {
+            let texture_size = allocator.size();
+            texture_pixels.clear();
+            texture_pixels.resize(texture_size.area() as usize * bpp, 0);
+            allocator.rasterize_layer(layer, format, &mut texture_pixels);
+
+            let row_len = texture_size.width as usize * bpp;
+            let x = idx * (slot.width + spacing) as usize * bpp;
+            for y in 0..texture_size.height as usize {
+                let dst = y * size.width as usize * bpp + x;
+                pixels[dst..dst + row_len].copy_from_slice(&texture_pixels[y * row_len..(y + 1) * row_len]);
+            }
+        }
+
+        (size, pixels)
+    }
+
     pub fn dump_svg(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.dump_svg_with_options(&SvgOptions::DEFAULT, &[], output)
+    }
+
+    /// Dump the textures in a grid, with the provided allocations drawn on top of them.
+    ///
+    /// Textures that are smaller than the size of the list are drawn at scale in the
+    /// top-left corner of their cell, standalone textures are scaled down to fit in it.
+    pub fn dump_svg_with_options(
+        &self,
+        options: &SvgOptions,
+        allocations: &[(Handle, SvgAllocation)],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
         use svg_fmt::*;
 
-        let size = 512.0;
-        let spacing = 10.0;
+        let spacing = options.spacing;
+        let cell_w = options.texture_width;
+        let cell_h = cell_w * self.size.height as f32 / self.size.width as f32;
+        let caption_h = if options.captions { svg::CAPTION_HEIGHT } else { 0.0 };
+
+        let (columns, rows) = svg::grid_size(self.textures.len(), options);
 
-        let svg_w = 2.0 * spacing + size * self.textures.len() as f32;
-        let svg_h = 2.0 * spacing + size;
+        let svg_w = spacing + (cell_w + spacing) * columns as f32;
+        let svg_h = spacing + (cell_h + caption_h + spacing) * rows as f32;
 
         writeln!(output, "{}", BeginSvg { w: svg_w, h: svg_h } )?;
 
@@ -295,17 +1060,67 @@ impl<Allocator: AtlasAllocator> AllocatorList<Allocator> {
                 .fill(rgb(30, 30, 30))
         )?;
 
-        let mut rect = crate::Rectangle {
-            min: point2(spacing, spacing).to_i32(),
-            max: point2(size + spacing, size + spacing).to_i32(),
+        for idx in 0..self.textures.len() {
+            let x = spacing + (idx % columns) as f32 * (cell_w + spacing);
+            let y = spacing + (idx / columns) as f32 * (cell_h + caption_h + spacing);
+            let cell = euclid::default::Box2D::new(point2(x, y), point2(x + cell_w, y + cell_h));
+            self.dump_texture_into_svg(idx, &cell, "", options, allocations, output)?;
+        }
+
+        writeln!(output, "{}", EndSvg)
+    }
+
+    /// Draw the texture at the provided index in the top-left corner of `cell`, followed
+    /// by its caption below the cell.
+    ///
+    /// A texture of the size of the list is as wide as the cell.
+    pub(crate) fn dump_texture_into_svg(
+        &self,
+        index: usize,
+        cell: &euclid::default::Box2D<f32>,
+        caption_prefix: &str,
+        options: &SvgOptions,
+        allocations: &[(Handle, SvgAllocation)],
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        let texture = &self.textures[index];
+        let (cell_w, cell_h) = (cell.width(), cell.height());
+        let (x, y) = (cell.min.x, cell.min.y);
+
+        let texture_size = texture.allocator.size();
+        let scale = (cell_w / self.size.width as f32)
+            .min(cell_w / texture_size.width as f32)
+            .min(cell_h / texture_size.height as f32);
+        let w = texture_size.width as f32 * scale;
+        let h = texture_size.height as f32 * scale;
+        let rect = crate::Rectangle {
+            min: point2(x, y).to_i32(),
+            max: point2(x + w, y + h).to_i32(),
         };
 
-        for texture in &self.textures {
-            texture.allocator.dump_into_svg(Some(&rect), output)?;
-            rect = rect.translate(vec2(size as i32 + spacing as i32, 0));
+        let texture_allocations: Vec<SvgAllocation> = allocations.iter()
+            .filter(|(handle, _)| handle.texture == texture.id)
+            .map(|(_, alloc)| *alloc)
+            .collect();
+
+        texture.allocator.dump_into_svg_with_options(Some(&rect), options, &texture_allocations, output)?;
+
+        if options.captions {
+            let occupancy = svg::occupancy(
+                texture.allocator.allocated_space() as i64,
+                texture_size.area() as i64,
+            );
+            let caption = if texture.standalone {
+                format!("{}Texture {} (standalone, {}x{}) - {:.1}% occupied", caption_prefix, texture.id.0, texture_size.width, texture_size.height, occupancy)
+            } else if texture_size == self.size {
+                format!("{}Texture {} - {:.1}% occupied", caption_prefix, texture.id.0, occupancy)
+            } else {
+                format!("{}Texture {} ({}x{}) - {:.1}% occupied", caption_prefix, texture.id.0, texture_size.width, texture_size.height, occupancy)
+            };
+            svg::write_caption(x, y + cell_h, &caption, output)?;
         }
 
-        writeln!(output, "{}", EndSvg)
+        Ok(())
     }
 }
 
@@ -359,6 +1174,53 @@ impl StatsRecorder {
         self.waste[h][w] += waste;
     }
 
+    /// Suggest options for the shelf allocator that suit the recorded sizes.
+    ///
+    /// The height steps split the allocations into four ranges of roughly the same
+    /// number of allocations, each of them rounded up to a quarter of its tallest size
+    /// class. Bins fit sixteen items of the median width.
+    pub fn suggest_shelf_options(&self) -> etagere::AllocatorOptions {
+        use etagere::{HeightStep, HeightSteps, ShelfBins, ShelfHeights};
+
+        let mut options = etagere::AllocatorOptions::DEFAULT;
+        if self.num_allocs == 0 {
+            return options;
+        }
+
+        // The smallest size class below which the provided fraction of the allocations is.
+        let quantile = |histogram: &[u64], num: u64, den: u64| {
+            let mut count = 0;
+            for (i, n) in histogram.iter().enumerate() {
+                count += n;
+                if count * den >= self.num_allocs * num {
+                    return i;
+                }
+            }
+
+            N - 1
+        };
+
+        let mut steps: Vec<HeightStep> = Vec::with_capacity(4);
+        for i in 0..4 {
+            let max_height = SIZES[quantile(&self.heights, i + 1, 4)];
+            // Quartiles in the same size class share an entry.
+            if steps.last().is_some_and(|step| step.max_height >= max_height) {
+                continue;
+            }
+            steps.push(HeightStep {
+                max_height,
+                // The largest power of two below a quarter of the height.
+                step: 1 << (31 - (max_height / 4).max(1).leading_zeros()),
+            });
+        }
+        steps.last_mut().unwrap().max_height = i32::MAX;
+
+        options.shelf_heights = ShelfHeights::Steps(HeightSteps::new(&steps).unwrap());
+        options.shelf_bins = ShelfBins::Width(SIZES[quantile(&self.widths, 1, 2)] * 16);
+
+        options
+    }
+
     pub fn print(&self) {
         println!("# Widths:");
         print!("size:\t");
@@ -426,3 +1288,536 @@ impl StatsRecorder {
         }
     }
 }
+
+/// A small xorshift generator so that the randomized tests are reproducible.
+#[cfg(test)]
+pub(crate) struct TestRng(u64);
+
+#[cfg(test)]
+impl TestRng {
+    pub fn new(seed: u64) -> Self {
+        TestRng((seed ^ 0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a value in [min, max] (inclusive).
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next() % (max - min + 1) as u64) as i32
+    }
+}
+
+/// Keeps track of which pixels are allocated, independently of the allocators' logic.
+#[cfg(test)]
+pub(crate) struct OccupancyOracle {
+    size: Size,
+    layers: Vec<Vec<bool>>,
+}
+
+#[cfg(test)]
+impl OccupancyOracle {
+    pub fn new(size: Size, num_layers: usize) -> Self {
+        OccupancyOracle {
+            size,
+            layers: vec![vec![false; size.area() as usize]; num_layers],
+        }
+    }
+
+    pub fn add(&mut self, layer: u16, rect: &Rectangle) {
+        assert!(
+            rect.min.x >= 0 && rect.min.y >= 0
+                && rect.max.x <= self.size.width && rect.max.y <= self.size.height,
+            "{:?} is out of the atlas bounds {:?}", rect, self.size,
+        );
+        assert!(!rect.is_empty(), "empty allocation {:?}", rect);
+
+        let pixels = &mut self.layers[layer as usize];
+        for y in rect.min.y..rect.max.y {
+            for x in rect.min.x..rect.max.x {
+                let idx = (y * self.size.width + x) as usize;
+                assert!(!pixels[idx], "{:?} overlaps another allocation in layer {}", rect, layer);
+                pixels[idx] = true;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, layer: u16, rect: &Rectangle) {
+        let pixels = &mut self.layers[layer as usize];
+        for y in rect.min.y..rect.max.y {
+            for x in rect.min.x..rect.max.x {
+                let idx = (y * self.size.width + x) as usize;
+                assert!(pixels[idx]);
+                pixels[idx] = false;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.iter().all(|pixels| pixels.iter().all(|allocated| !allocated))
+    }
+}
+
+/// Run a seeded sequence of random allocations and deallocations, checking the results
+/// against an occupancy oracle.
+///
+/// The validation callback is invoked regularly with the number of live allocations.
+/// Everything is deallocated at the end and the allocator must be empty again.
+#[cfg(test)]
+pub(crate) fn check_random_operations<A: AtlasAllocator>(
+    atlas: &mut A,
+    seed: u64,
+    num_ops: usize,
+    max_item_size: Size,
+    validate: &dyn Fn(&A, usize),
+) {
+    let mut rng = TestRng::new(seed);
+    let mut oracle = OccupancyOracle::new(atlas.size(), atlas.num_layers() as usize);
+    let mut live: Vec<ArrayAllocation> = Vec::new();
+
+    for i in 0..num_ops {
+        // Bias towards allocations until the atlas fills up.
+        if live.is_empty() || rng.range(0, 99) < 55 {
+            // Mostly small items with some large ones.
+            let max = if rng.range(0, 9) == 0 { max_item_size } else { max_item_size / 4 };
+            let size = size2(rng.range(1, max.width.max(1)), rng.range(1, max.height.max(1)));
+            if let Some(alloc) = atlas.allocate(size) {
+                let rect = &alloc.rectangle;
+                assert!(rect.width() >= size.width && rect.height() >= size.height);
+                oracle.add(alloc.layer, rect);
+                live.push(alloc);
+            }
+        } else {
+            let idx = rng.range(0, live.len() as i32 - 1) as usize;
+            let alloc = live.swap_remove(idx);
+            atlas.deallocate(alloc.id);
+            oracle.remove(alloc.layer, &alloc.rectangle);
+        }
+
+        if i % 16 == 0 {
+            validate(atlas, live.len());
+        }
+    }
+
+    while let Some(alloc) = live.pop() {
+        atlas.deallocate(alloc.id);
+        oracle.remove(alloc.layer, &alloc.rectangle);
+    }
+
+    validate(atlas, 0);
+    assert!(oracle.is_empty());
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn random_guillotine() {
+    for seed in 0..4 {
+        let mut atlas = GuillotineAllocator::new(size2(500, 300));
+        check_random_operations(&mut atlas, seed, 2000, size2(200, 150), &|atlas, live| {
+            let mut count = 0;
+            atlas.for_each_allocated_rectangle(|_, _| count += 1);
+            assert_eq!(count, live);
+        });
+    }
+}
+
+#[test]
+fn allocate_many() {
+    let mut rng = TestRng::new(3);
+    let mut sizes: Vec<Size> = (0..300).map(|_| size2(rng.range(4, 120), rng.range(4, 120))).collect();
+    sizes.push(size2(600, 10));
+
+    let mut online = AllocatorList::<ShelfAllocator>::new(size2(512, 512), etagere::AllocatorOptions::DEFAULT);
+    for size in &sizes {
+        online.allocate(*size);
+    }
+
+    let mut batch = AllocatorList::<ShelfAllocator>::new(size2(512, 512), etagere::AllocatorOptions::DEFAULT);
+    let results = batch.allocate_many(&sizes);
+    assert_eq!(results.len(), sizes.len());
+    assert_eq!(results.last(), Some(&Err(AllocError::TooLarge)));
+    assert!(batch.num_textures() <= online.num_textures());
+
+    let mut oracles = std::collections::HashMap::new();
+    for (size, result) in sizes.iter().zip(results.iter()).take(300) {
+        let alloc = result.unwrap();
+        let rect = &alloc.rectangle;
+        assert!(rect.width() >= size.width && rect.height() >= size.height);
+        oracles.entry(alloc.texture()).or_insert_with(|| OccupancyOracle::new(size2(512, 512), 1)).add(0, rect);
+    }
+    assert_eq!(oracles.len(), batch.num_textures());
+
+    for result in results.iter().take(300) {
+        batch.deallocate(result.unwrap().handle);
+    }
+    assert!(batch.is_empty());
+
+    let mut atlas = GuillotineAllocator::new(size2(256, 256));
+    let results = atlas.allocate_many(&sizes[..40]);
+    let mut oracle = OccupancyOracle::new(size2(256, 256), 1);
+    for result in results.iter().flatten() {
+        oracle.add(result.layer, &result.rectangle);
+    }
+    assert!(results.iter().any(|result| result == &Err(AllocError::OutOfSpace)));
+
+    // The results are those of the kept state: deallocating them empties the atlas.
+    for result in results.iter().flatten() {
+        AtlasAllocator::deallocate(&mut atlas, result.id);
+    }
+    assert!(AtlasAllocator::is_empty(&atlas));
+
+    let mut atlas = <ShelfAllocator as AtlasAllocator>::new(size2(256, 256), &etagere::AllocatorOptions::DEFAULT);
+    let results = atlas.allocate_many(&sizes[..40]);
+    assert_eq!(atlas.allocated_space(), results.iter().flatten().map(|result| result.rectangle.area()).sum::<i32>());
+    for result in results.iter().flatten() {
+        AtlasAllocator::deallocate(&mut atlas, result.id);
+    }
+    atlas.validate();
+    assert!(AtlasAllocator::is_empty(&atlas));
+}
+
+#[test]
+fn allocate_many_restores_the_list() {
+    let mut rng = TestRng::new(5);
+    let sizes: Vec<Size> = (0..100).map(|_| size2(rng.range(4, 100), rng.range(4, 100))).collect();
+
+    // Trying orderings must not leave empty textures behind, even with a release delay,
+    // nor change the ordering that is kept.
+    let mut results = Vec::new();
+    for &delay in &[0, 5] {
+        let mut list = AllocatorList::<ShelfAllocator>::new(size2(256, 256), etagere::AllocatorOptions::DEFAULT);
+        list.set_release_delay(delay);
+        let first = list.allocate(size2(200, 100)).unwrap();
+        let batch = list.allocate_many(&sizes);
+
+        let mut used: Vec<TextureId> = batch.iter().map(|result| result.unwrap().texture()).collect();
+        used.push(first.texture());
+        used.sort_by_key(TextureId::serialize);
+        used.dedup();
+        let mut ids = list.texture_ids();
+        ids.sort_by_key(TextureId::serialize);
+        assert_eq!(ids, used);
+        assert_eq!(ids.last().unwrap().serialize() as usize, ids.len());
+        assert_eq!(list.drain_events().count(), ids.len());
+
+        results.push(batch);
+    }
+    assert_eq!(results[0], results[1]);
+}
+
+#[test]
+fn wide_shelf_list() {
+    let mut atlas = WideShelfAllocator::with_options(size2(1000, 1000), &etagere::AllocatorOptions::DEFAULT);
+    check_random_operations(&mut atlas, 7, 2000, size2(120, 120), &|atlas, _| atlas.atlas().validate());
+
+    // Wider than what the compact layout supports.
+    let mut list = AllocatorList::<WideShelfAllocator>::new(size2(100_000, 1000), etagere::AllocatorOptions::DEFAULT);
+    let big = list.allocate(size2(70_000, 512)).unwrap();
+    assert_eq!(big.rectangle.size(), size2(70_000, 512));
+
+    // Table entries are reused, ids must keep mapping to the right allocations.
+    let mut handles = Vec::new();
+    for i in 0..5000 {
+        let alloc = list.allocate(size2(10, 10)).unwrap();
+        assert_eq!(alloc.texture(), big.texture());
+        if i % 2 == 0 {
+            list.deallocate(alloc.handle);
+        } else {
+            handles.push(alloc.handle);
+        }
+    }
+    let results = list.allocate_many(&[size2(30_000, 100), size2(20, 20)]);
+    handles.extend(results.iter().map(|result| result.unwrap().handle));
+
+    for handle in handles {
+        list.deallocate(handle);
+    }
+    list.deallocate(big.handle);
+    assert!(list.is_empty());
+}
+
+#[test]
+fn allocate_many_transient() {
+    use crate::transient::TransientAllocator;
+
+    let sizes = [size2(60, 50), size2(40, 50), size2(60, 50), size2(40, 50)];
+
+    let mut atlas = <TransientAllocator as AtlasAllocator>::new(size2(100, 100), &Default::default());
+    let results = atlas.allocate_many(&sizes);
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(atlas.allocated_space(), 100 * 100);
+    assert_eq!(atlas.num_allocations(), 4);
+
+    let mut list = AllocatorList::<TransientAllocator>::new(size2(100, 100), Default::default());
+    let results = list.allocate_many(&sizes);
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(list.num_textures(), 1);
+}
+
+#[test]
+fn multi_layer_list() {
+    use crate::tiled::{TiledAllocatorConfig, TiledAllocatorOptions, TileSizes};
+
+    let config = TiledAllocatorConfig {
+        tile_sizes: TileSizes::WrDefault,
+        layers: vec![
+            TiledAllocatorOptions { region_size: size2(64, 64) },
+            TiledAllocatorOptions { region_size: size2(128, 128) },
+        ],
+        slot_order: tiled::SlotOrder::DEFAULT,
+    };
+
+    let mut atlas = TiledAllocator::new(size2(128, 128), config.tile_sizes, &config.layers);
+    check_random_operations(&mut atlas, 2, 1000, size2(128, 128), &|atlas, _| atlas.validate());
+
+    let mut list = AllocatorList::<TiledAllocator>::new(size2(128, 128), config);
+    let a = list.allocate(size2(100, 100)).unwrap();
+    let b = list.allocate(size2(50, 50)).unwrap();
+    assert_eq!((a.layer, b.layer), (1, 0));
+    assert_eq!(a.texture(), b.texture());
+
+    // Standalone textures don't help the tiled allocator.
+    list.set_standalone_textures(Some(StandaloneSize::Exact));
+    assert!(list.allocate(size2(600, 600)).is_none());
+    assert_eq!(list.num_textures(), 1);
+
+    let (size, _) = list.rasterize(PixelFormat::Gray8);
+    assert_eq!(size, size2(128 * 2 + 4, 128));
+
+    // The large tile only fits in the second layer of a new texture.
+    let results = list.allocate_many(&[size2(100, 100), size2(60, 60)]);
+    let (c, d) = (results[0].unwrap(), results[1].unwrap());
+    assert_eq!((c.layer, d.layer), (1, 0));
+    assert_ne!(c.texture(), a.texture());
+    assert_eq!(d.texture(), a.texture());
+    assert_eq!(list.num_textures(), 2);
+}
+
+#[test]
+fn texture_array() {
+    let full = size2(64, 64);
+    let mut list = AllocatorList::<ShelfAllocator>::with_texture_array(full, etagere::AllocatorOptions::DEFAULT);
+    assert!(list.is_texture_array());
+    assert_eq!(list.array_resize(), None);
+
+    let allocs: Vec<Allocation> = (0..3).map(|_| list.allocate(full).unwrap()).collect();
+    let slots: Vec<u32> = allocs.iter().map(|alloc| list.array_layer(alloc.texture(), alloc.layer)).collect();
+    assert_eq!(slots, vec![0, 1, 2]);
+    assert_eq!(list.array_resize(), Some(ArrayResize::Grow(3)));
+    assert_eq!(list.array_resize(), None);
+
+    // Released slots are reused before growing.
+    list.deallocate(allocs[1].handle);
+    assert_eq!(list.array_resize(), None);
+    let reused = list.allocate(full).unwrap();
+    assert_eq!(reused.texture(), allocs[1].texture());
+    assert_eq!(list.array_resize(), None);
+
+    list.deallocate(allocs[2].handle);
+    assert_eq!(list.array_resize(), Some(ArrayResize::Shrink(2)));
+    list.deallocate(allocs[0].handle);
+    list.deallocate(reused.handle);
+    assert!(list.is_empty());
+    assert_eq!(list.array_resize(), Some(ArrayResize::Shrink(0)));
+
+    // Each texture of a multi-layer allocator takes consecutive layers.
+    let config = tiled::TiledAllocatorConfig {
+        tile_sizes: tiled::TileSizes::WrDefault,
+        layers: vec![tiled::TiledAllocatorOptions { region_size: full }; 2],
+        slot_order: tiled::SlotOrder::DEFAULT,
+    };
+    let mut list = AllocatorList::<TiledAllocator>::with_texture_array(full, config);
+    let allocs: Vec<Allocation> = (0..3).map(|_| list.allocate(full).unwrap()).collect();
+    let layers: Vec<u32> = allocs.iter().map(|alloc| list.array_layer(alloc.texture(), alloc.layer)).collect();
+    assert_eq!(layers, vec![0, 1, 2]);
+    assert_eq!(list.required_array_layers(), 4);
+}
+
+#[test]
+fn texture_events() {
+    use TextureEvent::*;
+
+    let full = size2(64, 64);
+    let mut list = AllocatorList::<ShelfAllocator>::new(full, etagere::AllocatorOptions::DEFAULT);
+
+    let a = list.allocate(full).unwrap();
+    assert_eq!(list.drain_events().collect::<Vec<_>>(), vec![TextureCreated(a.texture(), full)]);
+    list.deallocate(a.handle);
+    assert_eq!(list.drain_events().collect::<Vec<_>>(), vec![TextureReleased(a.texture())]);
+    assert_eq!(list.num_textures(), 0);
+
+    list.set_release_delay(2);
+    let b = list.allocate(full).unwrap();
+    list.deallocate(b.handle);
+    list.end_frame();
+    assert!(list.is_empty());
+    assert_eq!(list.num_textures(), 1);
+    assert_eq!(list.drain_events().collect::<Vec<_>>(), vec![TextureCreated(b.texture(), full)]);
+
+    // The empty texture is reused instead of creating a new one.
+    let c = list.allocate(full).unwrap();
+    assert_eq!(c.texture(), b.texture());
+    list.end_frame();
+    list.end_frame();
+    assert_eq!(list.drain_events().count(), 0);
+
+    list.deallocate(c.handle);
+    list.end_frame();
+    assert_eq!(list.drain_events().count(), 0);
+    list.end_frame();
+    assert_eq!(list.drain_events().collect::<Vec<_>>(), vec![TextureReleased(c.texture())]);
+    assert_eq!(list.num_textures(), 0);
+
+    // Only the textures that remain after trying several orderings are reported.
+    let mut list = AllocatorList::<ShelfAllocator>::with_texture_array(full, etagere::AllocatorOptions::DEFAULT);
+    let results = list.allocate_many(&[size2(40, 40), size2(40, 40), size2(30, 60), size2(20, 20)]);
+    assert!(results.iter().all(|result| result.is_ok()));
+    let mut created: Vec<TextureId> = list.drain_events().map(|event| match event {
+        TextureCreated(id, _) => id,
+        other => panic!("unexpected event {:?}", other),
+    }).collect();
+    created.sort_by_key(TextureId::serialize);
+    assert_eq!(created, list.texture_ids());
+}
+
+#[test]
+fn growing_textures() {
+    use TextureEvent::*;
+
+    let mut list = AllocatorList::<GuillotineAllocator>::new(size2(256, 256), guillotiere::DEFAULT_OPTIONS);
+    list.set_min_texture_size(size2(32, 32));
+
+    let a = list.allocate(size2(20, 20)).unwrap();
+    assert_eq!(a.texture_size, size2(32, 32));
+    let b = list.allocate(size2(30, 30)).unwrap();
+    assert_eq!((b.texture(), b.texture_size), (a.texture(), size2(64, 64)));
+    let c = list.allocate(size2(120, 120)).unwrap();
+    assert_eq!((c.texture(), c.texture_size), (a.texture(), size2(256, 256)));
+    let d = list.allocate(size2(250, 250)).unwrap();
+    assert_eq!(d.texture_size, size2(256, 256));
+    assert_eq!(list.drain_events().collect::<Vec<_>>(), vec![
+        TextureCreated(a.texture(), size2(32, 32)),
+        TextureGrown(a.texture(), size2(64, 64)),
+        TextureGrown(a.texture(), size2(128, 128)),
+        TextureGrown(a.texture(), size2(256, 256)),
+        TextureCreated(d.texture(), size2(256, 256)),
+    ]);
+
+    let mut svg = Vec::new();
+    list.dump_svg(&mut svg).unwrap();
+
+    // Textures are only grown by the ordering that is kept.
+    let mut list = AllocatorList::<GuillotineAllocator>::new(size2(1024, 1024), guillotiere::DEFAULT_OPTIONS);
+    list.set_min_texture_size(size2(32, 32));
+    let mut rng = TestRng::new(3);
+    let sizes: Vec<Size> = (0..30).map(|_| size2(rng.range(4, 60), rng.range(4, 60))).collect();
+    let results = list.allocate_many(&sizes);
+    let last_size = results.iter().map(|result| result.unwrap().texture_size).max_by_key(|size| size.area()).unwrap();
+    assert_eq!(list.num_textures(), 1);
+    assert_eq!(list.texture_size(list.texture_ids()[0]), Some(last_size));
+    assert!(list.drain_events().all(|event| match event {
+        TextureCreated(_, size) | TextureGrown(_, size) => size.area() <= last_size.area(),
+        TextureReleased(_) => false,
+    }));
+
+    // The shelf allocator can't grow, new textures get larger instead.
+    let mut list = AllocatorList::<ShelfAllocator>::new(size2(256, 256), etagere::AllocatorOptions::DEFAULT);
+    list.set_min_texture_size(size2(64, 64));
+    let sizes: Vec<Size> = [size2(64, 64), size2(64, 64), size2(10, 200), size2(60, 60)]
+        .iter()
+        .map(|&size| list.allocate(size).unwrap().texture_size)
+        .collect();
+    assert_eq!(sizes, vec![size2(64, 64), size2(128, 128), size2(256, 256), size2(128, 128)]);
+    assert_eq!(list.num_textures(), 3);
+
+    let (size, _) = list.rasterize(PixelFormat::Gray8);
+    assert_eq!(size, size2(256 * 3 + 8, 256));
+}
+
+#[test]
+fn standalone_textures() {
+    use TextureEvent::*;
+
+    let mut list = AllocatorList::<ShelfAllocator>::new(size2(256, 256), etagere::AllocatorOptions::DEFAULT);
+    assert!(list.allocate(size2(300, 10)).is_none());
+    assert_eq!(list.allocate_many(&[size2(300, 10)]), vec![Err(AllocError::TooLarge)]);
+
+    list.set_standalone_textures(Some(StandaloneSize::PowerOfTwo));
+    list.set_release_delay(2);
+    let a = list.allocate(size2(10, 10)).unwrap();
+    let b = list.allocate(size2(300, 100)).unwrap();
+    assert_eq!(b.texture_size, size2(512, 128));
+    assert!(list.is_standalone_texture(b.texture()));
+    assert!(!list.is_standalone_texture(a.texture()));
+
+    // Regular requests never go to standalone textures.
+    let c = list.allocate(size2(20, 20)).unwrap();
+    assert_eq!(c.texture(), a.texture());
+
+    let (size, _) = list.rasterize(PixelFormat::Gray8);
+    assert_eq!(size, size2(512 * 2 + 4, 256));
+    let mut svg = Vec::new();
+    list.dump_svg(&mut svg).unwrap();
+
+    // Released right away, without waiting for the release delay.
+    list.deallocate(b.handle);
+    assert_eq!(list.num_textures(), 1);
+    assert_eq!(list.drain_events().collect::<Vec<_>>(), vec![
+        TextureCreated(a.texture(), size2(256, 256)),
+        TextureCreated(b.texture(), size2(512, 128)),
+        TextureReleased(b.texture()),
+    ]);
+
+    list.set_standalone_textures(Some(StandaloneSize::Exact));
+    let results = list.allocate_many(&[size2(300, 10), size2(5, 5)]);
+    let d = results[0].unwrap();
+    assert_eq!(d.texture_size, size2(300, 10));
+    assert_eq!(results[1].unwrap().texture(), a.texture());
+
+    // Sides above 2^30 have no power of two that fits in an i32.
+    list.set_standalone_textures(Some(StandaloneSize::PowerOfTwo));
+    let num_textures = list.num_textures();
+    assert!(list.allocate(size2((1 << 30) + 1, 10)).is_none());
+    assert!(list.allocate(size2(10, i32::MAX)).is_none());
+    assert_eq!(list.num_textures(), num_textures);
+}
+
+#[test]
+fn suggest_shelf_options() {
+    use etagere::{HeightStep, HeightSteps, ShelfBins, ShelfHeights};
+
+    let mut stats = StatsRecorder::new();
+    assert_eq!(stats.suggest_shelf_options(), etagere::AllocatorOptions::DEFAULT);
+
+    for _ in 0..10 {
+        stats.allocate(size2(10, 8));
+        stats.allocate(size2(12, 15));
+        stats.allocate(size2(14, 30));
+        stats.allocate(size2(100, 100));
+    }
+
+    let options = stats.suggest_shelf_options();
+    assert_eq!(options.shelf_heights, ShelfHeights::Steps(HeightSteps::new(&[
+        HeightStep { max_height: 8, step: 2 },
+        HeightStep { max_height: 16, step: 4 },
+        HeightStep { max_height: 32, step: 8 },
+        HeightStep { max_height: i32::MAX, step: 32 },
+    ]).unwrap()));
+    assert_eq!(options.shelf_bins, ShelfBins::Width(12 * 16));
+
+    // Quartiles that fall in the same size class are merged.
+    let mut stats = StatsRecorder::new();
+    for _ in 0..10 {
+        stats.allocate(size2(10, 8));
+    }
+    stats.allocate(size2(10, 100));
+    let options = stats.suggest_shelf_options();
+    assert_eq!(options.shelf_heights, ShelfHeights::Steps(HeightSteps::new(&[
+        HeightStep { max_height: 8, step: 2 },
+        HeightStep { max_height: i32::MAX, step: 32 },
+    ]).unwrap()));
+}
@@ -0,0 +1,136 @@
+//! Dependency-free raster images of the atlases, mostly useful for automated tests.
+
+use crate::{Rectangle, Size};
+
+/// Color of the free space.
+pub const FREE_GRAY: u8 = 128;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// Three bytes per pixel.
+    Rgb8,
+    /// One byte per pixel.
+    Gray8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Gray8 => 1,
+        }
+    }
+}
+
+/// A distinct color for each allocation index, never gray.
+pub fn allocation_color(index: usize) -> [u8; 3] {
+    // Walk around the hue circle using the golden ratio so that consecutive
+    // allocations get very different colors.
+    let hue = (index as f32 * 0.618_034).fract() * 6.0;
+    let lightness = [1.0, 0.75, 0.5][(index / 7) % 3];
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let c = |v: f32| (40.0 + v * 215.0 * lightness) as u8;
+
+    [c(r), c(g), c(b)]
+}
+
+/// A gray level for each allocation index, never the same as the free space.
+pub fn allocation_gray(index: usize) -> u8 {
+    const LEVELS: [u8; 6] = [255, 32, 224, 64, 192, 96];
+    LEVELS[index % LEVELS.len()]
+}
+
+/// Fill the free space and draw the provided rectangles in a tightly packed image of
+/// the provided size.
+///
+/// Rectangles are clipped to the image.
+pub fn rasterize_rectangles(
+    size: Size,
+    rectangles: &mut dyn Iterator<Item = Rectangle>,
+    format: PixelFormat,
+    pixels: &mut [u8],
+) {
+    let bpp = format.bytes_per_pixel();
+    assert!(pixels.len() >= size.area() as usize * bpp, "The pixel buffer is too small.");
+
+    for byte in &mut pixels[..size.area() as usize * bpp] {
+        *byte = FREE_GRAY;
+    }
+
+    let bounds = Rectangle { min: crate::point2(0, 0), max: size.to_vector().to_point() };
+    for (index, rect) in rectangles.enumerate() {
+        let rect = match rect.intersection(&bounds) {
+            Some(rect) => rect,
+            None => continue,
+        };
+
+        let rgb = allocation_color(index);
+        let gray = [allocation_gray(index)];
+        let color: &[u8] = match format {
+            PixelFormat::Rgb8 => &rgb,
+            PixelFormat::Gray8 => &gray,
+        };
+
+        for y in rect.min.y..rect.max.y {
+            let row = (y * size.width) as usize * bpp;
+            for x in rect.min.x..rect.max.x {
+                let offset = row + x as usize * bpp;
+                pixels[offset..offset + bpp].copy_from_slice(color);
+            }
+        }
+    }
+}
+
+/// Write RGB pixels in the binary PPM (P6) format.
+pub fn write_ppm(size: Size, pixels: &[u8], output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    write!(output, "P6\n{} {}\n255\n", size.width, size.height)?;
+    output.write_all(&pixels[..size.area() as usize * 3])
+}
+
+/// Write grayscale pixels in the binary PGM (P5) format.
+pub fn write_pgm(size: Size, pixels: &[u8], output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    write!(output, "P5\n{} {}\n255\n", size.width, size.height)?;
+    output.write_all(&pixels[..size.area() as usize])
+}
+
+#[test]
+fn rasterize_shelves() {
+    use crate::{AtlasAllocator, ShelfAllocator, size2};
+
+    let mut atlas = ShelfAllocator::new(size2(64, 64));
+    atlas.allocate(size2(16, 16)).unwrap();
+    atlas.allocate(size2(64, 32)).unwrap();
+
+    let mut pixels = vec![0; 64 * 64];
+    atlas.rasterize(PixelFormat::Gray8, &mut pixels);
+
+    let allocated = pixels.iter().filter(|&&p| p != FREE_GRAY).count() as i32;
+    assert_eq!(allocated, atlas.allocated_space());
+
+    let mut rgb = vec![0; 64 * 64 * 3];
+    atlas.rasterize(PixelFormat::Rgb8, &mut rgb);
+    let free = rgb.chunks(3).filter(|p| p == &[FREE_GRAY; 3]).count() as i32;
+    assert_eq!(free, 64 * 64 - atlas.allocated_space());
+
+    let mut ppm = Vec::new();
+    write_ppm(size2(64, 64), &rgb, &mut ppm).unwrap();
+    assert!(ppm.starts_with(b"P6\n64 64\n255\n"));
+    assert_eq!(ppm.len(), 13 + 64 * 64 * 3);
+}
+
+#[test]
+fn distinct_colors() {
+    for i in 0..64 {
+        let [r, g, b] = allocation_color(i);
+        assert!(r != g || g != b, "allocation color {} is gray", i);
+        assert!(allocation_gray(i) != FREE_GRAY);
+    }
+}
@@ -0,0 +1,223 @@
+//! Replay a sequence of allocations and deallocations into a self-contained HTML page
+//! that animates the state of the atlas over time.
+
+use crate::{AllocatorList, AtlasAllocator, Handle, Rectangle, Size, SvgAllocation, SvgOptions};
+use crate::svg;
+use std::collections::HashMap;
+
+/// An operation of a recorded session.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReplayOp {
+    Allocate { name: String, size: Size },
+    Deallocate { name: String },
+}
+
+struct Frame {
+    caption: String,
+    svg: String,
+}
+
+/// Accumulates SVG frames and writes them as an HTML page with a scrubber.
+pub struct ReplayRecorder {
+    frames: Vec<Frame>,
+}
+
+impl ReplayRecorder {
+    pub fn new() -> Self {
+        ReplayRecorder { frames: Vec::new() }
+    }
+
+    /// Add a frame containing a complete SVG document.
+    pub fn add_frame(&mut self, caption: &str, svg: String) {
+        self.frames.push(Frame {
+            caption: caption.to_string(),
+            svg,
+        });
+    }
+
+    /// Add a frame showing the current state of an allocator list.
+    pub fn record<A: AtlasAllocator>(
+        &mut self,
+        caption: &str,
+        atlas: &AllocatorList<A>,
+        options: &SvgOptions,
+        allocations: &[(Handle, SvgAllocation)],
+    ) -> std::io::Result<()> {
+        let mut svg = Vec::new();
+        atlas.dump_svg_with_options(options, allocations, &mut svg)?;
+        self.add_frame(caption, String::from_utf8_lossy(&svg).into_owned());
+
+        Ok(())
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn write_html(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(output, "<!DOCTYPE html>")?;
+        writeln!(output, "<html><head><meta charset=\"utf-8\"><title>Atlas replay</title>")?;
+        writeln!(output, "<style>")?;
+        writeln!(output, "body {{ background: #1e1e1e; color: #ddd; font-family: sans-serif; }}")?;
+        writeln!(output, ".frame {{ display: none; }} .frame svg {{ max-width: 100%; max-height: 85vh; }}")?;
+        writeln!(output, "#scrubber {{ width: 80%; }}")?;
+        writeln!(output, "</style></head><body>")?;
+        writeln!(
+            output,
+            "<div><button id=\"play\">Play</button> <input id=\"scrubber\" type=\"range\" min=\"0\" max=\"{}\" value=\"0\"> <span id=\"caption\"></span></div>",
+            self.frames.len().saturating_sub(1),
+        )?;
+
+        for (idx, frame) in self.frames.iter().enumerate() {
+            writeln!(output, "<div class=\"frame\" id=\"frame{}\" data-caption=\"{}\">", idx, svg::escape(&frame.caption))?;
+            output.write_all(frame.svg.as_bytes())?;
+            writeln!(output, "</div>")?;
+        }
+
+        writeln!(output, "<script>")?;
+        writeln!(output, "{}", SCRIPT)?;
+        writeln!(output, "</script></body></html>")
+    }
+}
+
+impl Default for ReplayRecorder {
+    fn default() -> Self {
+        ReplayRecorder::new()
+    }
+}
+
+const SCRIPT: &str = r#"
+var scrubber = document.getElementById("scrubber");
+var caption = document.getElementById("caption");
+var play = document.getElementById("play");
+var current = null;
+var timer = null;
+function show(idx) {
+    if (current) { current.style.display = "none"; }
+    current = document.getElementById("frame" + idx);
+    if (!current) { return; }
+    current.style.display = "block";
+    caption.textContent = (+idx + 1) + "/" + (+scrubber.max + 1) + ": " + current.dataset.caption;
+}
+scrubber.oninput = function() { show(scrubber.value); };
+play.onclick = function() {
+    if (timer) {
+        clearInterval(timer);
+        timer = null;
+        play.textContent = "Play";
+        return;
+    }
+    play.textContent = "Pause";
+    timer = setInterval(function() {
+        if (+scrubber.value >= +scrubber.max) { scrubber.value = 0; } else { scrubber.value = +scrubber.value + 1; }
+        show(scrubber.value);
+    }, 100);
+};
+show(0);
+"#;
+
+/// Apply a sequence of operations to an allocator list and write an HTML animation
+/// of the result, with a frame every `frame_interval` operations.
+///
+/// Allocations are labeled with their names. Allocating a name that is still live
+/// deallocates its previous allocation first. Deallocations of unknown names are ignored.
+pub fn replay<A: AtlasAllocator>(
+    atlas: &mut AllocatorList<A>,
+    ops: &[ReplayOp],
+    options: &SvgOptions,
+    frame_interval: usize,
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    let frame_interval = frame_interval.max(1);
+    let mut recorder = ReplayRecorder::new();
+    let mut live: HashMap<&str, (Handle, Rectangle, u16, u32)> = HashMap::new();
+
+    record_frame(&mut recorder, "start", atlas, options, &live)?;
+
+    for (idx, op) in ops.iter().enumerate() {
+        let caption = match op {
+            ReplayOp::Allocate { name, size } => {
+                if let Some((handle, _, _, _)) = live.remove(name.as_str()) {
+                    atlas.deallocate(handle);
+                }
+
+                match atlas.allocate(*size) {
+                    Some(alloc) => {
+                        live.insert(name, (alloc.handle, alloc.rectangle, alloc.layer, idx as u32));
+                        format!("allocate {} ({}x{})", name, size.width, size.height)
+                    }
+                    None => format!("failed to allocate {} ({}x{})", name, size.width, size.height),
+                }
+            }
+            ReplayOp::Deallocate { name } => {
+                if let Some((handle, _, _, _)) = live.remove(name.as_str()) {
+                    atlas.deallocate(handle);
+                }
+                format!("deallocate {}", name)
+            }
+        };
+
+        if (idx + 1) % frame_interval == 0 || idx + 1 == ops.len() {
+            record_frame(&mut recorder, &caption, atlas, options, &live)?;
+        }
+    }
+
+    recorder.write_html(output)
+}
+
+fn record_frame<A: AtlasAllocator>(
+    recorder: &mut ReplayRecorder,
+    caption: &str,
+    atlas: &AllocatorList<A>,
+    options: &SvgOptions,
+    live: &HashMap<&str, (Handle, Rectangle, u16, u32)>,
+) -> std::io::Result<()> {
+    let allocations: Vec<(Handle, SvgAllocation)> = live
+        .iter()
+        .map(|(name, &(handle, rectangle, layer, age))| (handle, SvgAllocation { rectangle, layer, label: Some(*name), age }))
+        .collect();
+
+    recorder.record(caption, atlas, options, &allocations)
+}
+
+#[test]
+fn replay_frames() {
+    use crate::{ShelfAllocator, size2};
+
+    let ops = vec![
+        ReplayOp::Allocate { name: "a".to_string(), size: size2(100, 100) },
+        ReplayOp::Allocate { name: "b".to_string(), size: size2(256, 256) },
+        ReplayOp::Deallocate { name: "a".to_string() },
+        ReplayOp::Deallocate { name: "b".to_string() },
+    ];
+
+    let mut atlas = AllocatorList::<ShelfAllocator>::new(size2(256, 256), crate::etagere::AllocatorOptions::DEFAULT);
+    let mut html = Vec::new();
+    replay(&mut atlas, &ops, &SvgOptions::DEFAULT, 1, &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+
+    // One frame for the initial state and one per operation.
+    assert_eq!(html.matches("<div class=\"frame\"").count(), 5);
+    assert_eq!(html.matches("<svg").count(), 5);
+    assert!(html.contains("max=\"4\""));
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn replay_reused_name() {
+    use crate::{ShelfAllocator, size2};
+
+    let ops = vec![
+        ReplayOp::Allocate { name: "a".to_string(), size: size2(100, 100) },
+        ReplayOp::Allocate { name: "a".to_string(), size: size2(50, 50) },
+        ReplayOp::Deallocate { name: "a".to_string() },
+    ];
+
+    let mut atlas = AllocatorList::<ShelfAllocator>::new(size2(256, 256), crate::etagere::AllocatorOptions::DEFAULT);
+    let mut html = Vec::new();
+    replay(&mut atlas, &ops, &SvgOptions::DEFAULT, 1, &mut html).unwrap();
+
+    // The first allocation doesn't leak.
+    assert!(atlas.is_empty());
+}
@@ -0,0 +1,249 @@
+//! Options and helpers shared by the SVG dumps of the various allocators.
+
+use crate::{Rectangle, Size};
+use svg_fmt::*;
+
+/// The kind of allocator that produced a dump, used when coloring by allocator kind.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AllocatorKind {
+    Guillotine,
+    Shelf,
+    Tiled,
+    Transient,
+}
+
+/// How allocated rectangles are colored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Coloring {
+    /// Use the allocator's usual colors.
+    Default,
+    /// One color per power-of-two size class of the largest side of the allocation.
+    SizeClass,
+    /// From dark for the oldest allocations to bright for the most recent ones.
+    Age,
+    /// One color per kind of allocator.
+    AllocatorKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SvgOptions {
+    /// Width of each texture in the output.
+    ///
+    /// Default value: 512.
+    pub texture_width: f32,
+    /// Space between textures.
+    ///
+    /// Default value: 10.
+    pub spacing: f32,
+    /// Number of textures per row. Zero picks a roughly square grid.
+    ///
+    /// Default value: 0.
+    pub columns: usize,
+    /// Default value: `Coloring::Default`.
+    pub coloring: Coloring,
+    /// Write the label of each allocation on top of it.
+    ///
+    /// Default value: true.
+    pub labels: bool,
+    /// Write the texture id and occupancy under each texture.
+    ///
+    /// Default value: true.
+    pub captions: bool,
+}
+
+impl SvgOptions {
+    pub const DEFAULT: Self = SvgOptions {
+        texture_width: 512.0,
+        spacing: 10.0,
+        columns: 0,
+        coloring: Coloring::Default,
+        labels: true,
+        captions: true,
+    };
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions::DEFAULT
+    }
+}
+
+/// Information about an allocation that the allocators don't track themselves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SvgAllocation<'l> {
+    /// The allocated rectangle, in the coordinates of its atlas.
+    pub rectangle: Rectangle,
+    /// The layer of the atlas that contains the rectangle.
+    pub layer: u16,
+    pub label: Option<&'l str>,
+    /// Any value that increases with time, for example the order of the allocations.
+    pub age: u32,
+}
+
+pub const CAPTION_HEIGHT: f32 = 20.0;
+
+/// The default color of allocated space.
+pub(crate) fn allocated_color(options: &SvgOptions, kind: AllocatorKind) -> Color {
+    match options.coloring {
+        Coloring::AllocatorKind => kind_color(kind),
+        _ => rgb(70, 70, 180),
+    }
+}
+
+pub(crate) fn kind_color(kind: AllocatorKind) -> Color {
+    match kind {
+        AllocatorKind::Guillotine => rgb(70, 140, 70),
+        AllocatorKind::Shelf => rgb(70, 70, 180),
+        AllocatorKind::Tiled => rgb(170, 110, 40),
+        AllocatorKind::Transient => rgb(150, 60, 150),
+    }
+}
+
+fn size_class_color(size: Size) -> Color {
+    const PALETTE: [(u8, u8, u8); 8] = [
+        (60, 60, 200),
+        (40, 130, 220),
+        (30, 170, 160),
+        (60, 170, 60),
+        (170, 170, 40),
+        (220, 130, 30),
+        (210, 60, 60),
+        (170, 60, 170),
+    ];
+
+    let max_side = size.width.max(size.height).max(1) as u32;
+    // 1..=8 -> 0, 9..=16 -> 1, etc.
+    let class = (max_side.next_power_of_two().trailing_zeros() as usize).saturating_sub(3);
+    let (r, g, b) = PALETTE[class.min(PALETTE.len() - 1)];
+
+    rgb(r, g, b)
+}
+
+fn age_color(age: u32, min_age: u32, max_age: u32) -> Color {
+    let t = if max_age > min_age {
+        (age - min_age) as f32 / (max_age - min_age) as f32
+    } else {
+        1.0
+    };
+    let c = |dark: f32, bright: f32| (dark + (bright - dark) * t) as u8;
+
+    rgb(c(40.0, 250.0), c(30.0, 200.0), c(90.0, 60.0))
+}
+
+/// Draw the provided allocations on top of a dump of their atlas.
+///
+/// If a rectangle is provided, translate and scale the output to fit it.
+pub fn dump_allocations_into_svg(
+    atlas_size: Size,
+    rect: Option<&Rectangle>,
+    kind: AllocatorKind,
+    options: &SvgOptions,
+    allocations: &[SvgAllocation],
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    let (sx, sy, tx, ty) = if let Some(rect) = rect {
+        (
+            rect.size().width as f32 / atlas_size.width as f32,
+            rect.size().height as f32 / atlas_size.height as f32,
+            rect.min.x as f32,
+            rect.min.y as f32,
+        )
+    } else {
+        (1.0, 1.0, 0.0, 0.0)
+    };
+
+    let min_age = allocations.iter().map(|alloc| alloc.age).min().unwrap_or(0);
+    let max_age = allocations.iter().map(|alloc| alloc.age).max().unwrap_or(0);
+
+    for alloc in allocations {
+        let x = alloc.rectangle.min.x as f32 * sx + tx;
+        let y = alloc.rectangle.min.y as f32 * sy + ty;
+        let w = alloc.rectangle.width() as f32 * sx;
+        let h = alloc.rectangle.height() as f32 * sy;
+
+        let fill = match options.coloring {
+            Coloring::Default => None,
+            Coloring::SizeClass => Some(size_class_color(alloc.rectangle.size())),
+            Coloring::Age => Some(age_color(alloc.age, min_age, max_age)),
+            Coloring::AllocatorKind => Some(kind_color(kind)),
+        };
+
+        if let Some(color) = fill {
+            writeln!(
+                output,
+                r#"    {}"#,
+                rectangle(x, y, w, h)
+                    .fill(color)
+                    .stroke(Stroke::Color(black(), 1.0))
+            )?;
+        }
+
+        if let (true, Some(label)) = (options.labels, alloc.label) {
+            // Shrink the text so that it roughly fits in the rectangle.
+            let font_size = (w / (label.chars().count().max(1) as f32 * 0.6)).min(h * 0.8).min(14.0);
+            if font_size >= 3.0 {
+                writeln!(
+                    output,
+                    r#"    {}"#,
+                    text(x + w * 0.5, y + h * 0.5 + font_size * 0.35, escape(label))
+                        .size(font_size)
+                        .color(white())
+                        .align(Align::Center)
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a line of text under a texture.
+pub(crate) fn write_caption(x: f32, y: f32, caption: &str, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    writeln!(
+        output,
+        r#"    {}"#,
+        text(x, y + CAPTION_HEIGHT * 0.7, escape(caption))
+            .size(CAPTION_HEIGHT * 0.6)
+            .color(rgb(220, 220, 220))
+    )
+}
+
+/// Percentage of the provided area that is allocated.
+pub(crate) fn occupancy(allocated_space: i64, total_space: i64) -> f32 {
+    if total_space <= 0 {
+        return 0.0;
+    }
+
+    allocated_space as f32 * 100.0 / total_space as f32
+}
+
+/// Number of columns and rows of the grid of textures.
+pub(crate) fn grid_size(num_textures: usize, options: &SvgOptions) -> (usize, usize) {
+    if num_textures == 0 {
+        return (0, 0);
+    }
+
+    let columns = if options.columns > 0 {
+        options.columns
+    } else {
+        (num_textures as f32).sqrt().ceil() as usize
+    }.min(num_textures);
+
+    (columns, num_textures.div_ceil(columns))
+}
+
+/// Escape text for use in XML content and attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[test]
+fn grid() {
+    let options = SvgOptions::DEFAULT;
+    assert_eq!(grid_size(0, &options), (0, 0));
+    assert_eq!(grid_size(1, &options), (1, 1));
+    assert_eq!(grid_size(3, &options), (2, 2));
+    assert_eq!(grid_size(20, &options), (5, 4));
+    assert_eq!(grid_size(20, &SvgOptions { columns: 8, .. options }), (8, 3));
+    assert_eq!(grid_size(2, &SvgOptions { columns: 8, .. options }), (2, 1));
+}
@@ -1,38 +1,268 @@
 use crate::{Rectangle, Size, size2, Point, point2};
 //use crate::free_list::*;
 
-use crate::AllocId;
+use crate::{AllocId, ArrayAllocation};
+use crate::svg::{self, SvgAllocation, SvgOptions};
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TiledAllocatorOptions {
     pub region_size: Size,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// The order in which the free tiles of a region are allocated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SlotOrder {
+    /// Regions are filled from the top-left corner, row by row, and the most recently
+    /// freed tile is reused first.
+    ///
+    /// This is the cheapest policy but the order becomes arbitrary after some churn.
+    Stack,
+    /// Always allocate the free tile that comes first in row-major order, so that
+    /// allocations are packed in as few rows as possible.
+    RowMajor,
+    /// Always allocate the free tile that comes first in Morton (Z-curve) order, so that
+    /// allocations made together cluster in small squares.
+    Morton,
+}
+
+impl SlotOrder {
+    pub const DEFAULT: Self = SlotOrder::Stack;
+}
+
+impl Default for SlotOrder {
+    fn default() -> Self {
+        SlotOrder::DEFAULT
+    }
+}
+
+/// Parameters to create a `TiledAllocator` with, one set of options per layer.
+#[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct ArrayAllocation {
-    pub id: AllocId,
+pub struct TiledAllocatorConfig {
+    pub tile_sizes: TileSizes,
+    pub layers: Vec<TiledAllocatorOptions>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub slot_order: SlotOrder,
+}
+
+impl TiledAllocatorConfig {
+    pub fn single_layer(tile_sizes: TileSizes, region_size: Size) -> Self {
+        TiledAllocatorConfig {
+            tile_sizes,
+            layers: vec![TiledAllocatorOptions { region_size }],
+            slot_order: SlotOrder::DEFAULT,
+        }
+    }
+}
+
+/// The free tiles of a region.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+enum FreeSlots {
+    Stack(Vec<(u8, u8)>),
+    /// One bit per tile, set if the tile is free, at the index of the tile in row-major
+    /// or Morton order.
+    Bitmap {
+        bits: Vec<u64>,
+        len: u16,
+        morton: bool,
+        grid_width: u16,
+    },
+}
+
+impl FreeSlots {
+    fn new(order: SlotOrder) -> Self {
+        match order {
+            SlotOrder::Stack => FreeSlots::Stack(Vec::new()),
+            SlotOrder::RowMajor | SlotOrder::Morton => FreeSlots::Bitmap {
+                bits: Vec::new(),
+                len: 0,
+                morton: order == SlotOrder::Morton,
+                grid_width: 0,
+            },
+        }
+    }
+
+    /// Mark all tiles of a grid as free.
+    fn reset(&mut self, n_tiles_x: i32, n_tiles_y: i32) {
+        match self {
+            FreeSlots::Stack(slots) => {
+                slots.clear();
+                slots.reserve((n_tiles_x * n_tiles_y) as usize);
+                // Reversed so that the top-left slot is popped first.
+                for y in (0..n_tiles_y as u8).rev() {
+                    for x in (0..n_tiles_x as u8).rev() {
+                        slots.push((x, y));
+                    }
+                }
+            }
+            FreeSlots::Bitmap { bits, len, morton, grid_width } => {
+                let num_bits = if *morton {
+                    let side = (n_tiles_x.max(n_tiles_y) as usize).next_power_of_two();
+                    side * side
+                } else {
+                    (n_tiles_x * n_tiles_y) as usize
+                };
+                *grid_width = n_tiles_x as u16;
+                *len = (n_tiles_x * n_tiles_y) as u16;
+                bits.clear();
+                bits.resize(num_bits.div_ceil(64), 0);
+                for y in 0..n_tiles_y as u8 {
+                    for x in 0..n_tiles_x as u8 {
+                        let index = slot_index((x, y), *morton, *grid_width);
+                        bits[index / 64] |= 1 << (index % 64);
+                    }
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<(u8, u8)> {
+        match self {
+            FreeSlots::Stack(slots) => slots.pop(),
+            FreeSlots::Bitmap { bits, len, morton, grid_width } => {
+                let word = bits.iter().position(|&word| word != 0)?;
+                let bit = bits[word].trailing_zeros();
+                bits[word] &= !(1 << bit);
+                *len -= 1;
+
+                Some(slot_at(word * 64 + bit as usize, *morton, *grid_width))
+            }
+        }
+    }
+
+    fn push(&mut self, slot: (u8, u8)) {
+        match self {
+            FreeSlots::Stack(slots) => slots.push(slot),
+            FreeSlots::Bitmap { bits, len, morton, grid_width } => {
+                let index = slot_index(slot, *morton, *grid_width);
+                debug_assert_eq!(bits[index / 64] & (1 << (index % 64)), 0, "slot {:?} is already free", slot);
+                bits[index / 64] |= 1 << (index % 64);
+                *len += 1;
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            FreeSlots::Stack(slots) => slots.len(),
+            FreeSlots::Bitmap { len, .. } => *len as usize,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self) {
+        match self {
+            FreeSlots::Stack(slots) => slots.clear(),
+            FreeSlots::Bitmap { bits, len, .. } => {
+                bits.clear();
+                *len = 0;
+            }
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u8, u8)> + '_> {
+        match self {
+            FreeSlots::Stack(slots) => Box::new(slots.iter().cloned()),
+            FreeSlots::Bitmap { bits, morton, grid_width, .. } => {
+                let (morton, grid_width) = (*morton, *grid_width);
+                Box::new(bits.iter().enumerate().flat_map(move |(word, &value)| {
+                    (0..64)
+                        .filter(move |bit| value & (1 << bit) != 0)
+                        .map(move |bit| slot_at(word * 64 + bit, morton, grid_width))
+                }))
+            }
+        }
+    }
+}
+
+fn slot_index(slot: (u8, u8), morton: bool, grid_width: u16) -> usize {
+    if morton {
+        (spread_bits(slot.0) | spread_bits(slot.1) << 1) as usize
+    } else {
+        slot.1 as usize * grid_width as usize + slot.0 as usize
+    }
+}
+
+fn slot_at(index: usize, morton: bool, grid_width: u16) -> (u8, u8) {
+    if morton {
+        (compact_bits(index as u16), compact_bits((index >> 1) as u16))
+    } else {
+        ((index % grid_width as usize) as u8, (index / grid_width as usize) as u8)
+    }
+}
+
+/// Insert a zero bit between each bit of the value.
+fn spread_bits(value: u8) -> u16 {
+    let mut v = value as u16;
+    v = (v | (v << 4)) & 0x0F0F;
+    v = (v | (v << 2)) & 0x3333;
+    v = (v | (v << 1)) & 0x5555;
+    v
+}
+
+/// The inverse of `spread_bits`, ignoring odd bits.
+fn compact_bits(value: u16) -> u8 {
+    let mut v = value & 0x5555;
+    v = (v | (v >> 1)) & 0x3333;
+    v = (v | (v >> 2)) & 0x0F0F;
+    v = (v | (v >> 4)) & 0x00FF;
+    v as u8
+}
+
+/// How a layer is used for a tile size, see `TiledAllocator::usage`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileUsage {
+    pub layer: u16,
+    pub tile_size: Size,
+    /// Number of times a region of the layer was initialized with this tile size.
+    pub initialized_regions: u32,
+    /// Number of regions of the layer that currently hold tiles of this size.
+    pub live_regions: u32,
+    /// Number of tiles of this size currently allocated in the layer.
+    pub live_tiles: u32,
+}
+
+/// A region to reinitialize, see `TiledAllocator::select_region_to_evict`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Eviction {
+    pub region: u16,
     pub layer: u16,
-    pub rectangle: Rectangle,    
+    /// The live allocations of the region.
+    pub allocations: Vec<ArrayAllocation>,
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct TiledRegion {
-    free_slots: Vec<(u8, u8)>,
+    free_slots: FreeSlots,
     tile_size: Size,
     origin: Point,
     size: Size,
     num_tiles: u16,
     index: u16,
     layer: u16,
+    /// Set when the region is part of a tile that spans several regions, to the index of
+    /// the region at the top-left corner of the block which holds the allocation.
+    covered_by: Option<u16>,
 }
 
 impl TiledRegion {
     fn allocate(&mut self) -> Option<ArrayAllocation> {
         let slot = self.free_slots.pop()?;
 
-        let x = self.origin.x + slot.0 as i32 * self.tile_size.width;
-        let y = self.origin.y + slot.1 as i32 * self.tile_size.height;
+        Some(self.allocation(slot))
+    }
+
+    fn allocation(&self, slot: (u8, u8)) -> ArrayAllocation {
+        // The origin is expressed in number of regions.
+        let x = self.origin.x * self.size.width + slot.0 as i32 * self.tile_size.width;
+        let y = self.origin.y * self.size.height + slot.1 as i32 * self.tile_size.height;
 
         debug_assert_eq!(self.index & 0xFFFF, self.index);
 
@@ -42,7 +272,7 @@ impl TiledRegion {
             | (slot.1 as u32) << 24
         );
 
-        Some(ArrayAllocation {
+        ArrayAllocation {
             id,
             layer: self.layer,
             rectangle: Rectangle {
@@ -52,50 +282,86 @@ impl TiledRegion {
                     y + self.tile_size.height,
                 ),
             }
-        })
+        }
     }
 
-    fn init(&mut self, tile_size: Size) {
-        let n_tiles_x = self.size.width / tile_size.width;
-        let n_tiles_y = self.size.height / tile_size.height;
-        let n_tiles = (n_tiles_x * n_tiles_y) as usize;
+    /// Number of tiles in each direction. A tile that is larger than the region spans
+    /// several regions and counts as a single tile of its top-left region.
+    fn tile_grid(&self) -> (i32, i32) {
+        (
+            (self.size.width / self.tile_size.width).max(1),
+            (self.size.height / self.tile_size.height).max(1),
+        )
+    }
 
-        self.tile_size = tile_size;
-        self.free_slots.clear();
-        self.free_slots.reserve(n_tiles);
-        self.num_tiles = n_tiles as u16;
+    fn is_spanning(&self) -> bool {
+        self.tile_size.width > self.size.width || self.tile_size.height > self.size.height
+    }
+
+    /// Invoke a callback for each allocated tile.
+    fn for_each_allocation(&self, mut callback: impl FnMut(ArrayAllocation)) {
+        if self.tile_size.is_empty() || self.is_empty() {
+            return;
+        }
 
-        for y in 0..(n_tiles_y as u8) {
-            for x in 0..(n_tiles_x as u8) {
-                self.free_slots.push((x, y));
+        let (n_tiles_x, n_tiles_y) = self.tile_grid();
+
+        let mut allocated = vec![true; self.num_tiles as usize];
+        for (x, y) in self.free_slots.iter() {
+            allocated[y as usize * n_tiles_x as usize + x as usize] = false;
+        }
+
+        for y in 0..n_tiles_y {
+            for x in 0..n_tiles_x {
+                if allocated[(y * n_tiles_x + x) as usize] {
+                    callback(self.allocation((x as u8, y as u8)));
+                }
             }
         }
+    }
 
-        println!(" == init {:?} tile_size {:?} -> num_tiles: {:?}", self.size, tile_size, n_tiles);
+    fn num_allocated_tiles(&self) -> usize {
+        self.num_tiles as usize - self.free_slots.len()
+    }
+
+    fn init(&mut self, tile_size: Size) {
+        self.tile_size = tile_size;
+        let (n_tiles_x, n_tiles_y) = self.tile_grid();
+        self.num_tiles = (n_tiles_x * n_tiles_y) as u16;
+        self.free_slots.reset(n_tiles_x, n_tiles_y);
     }
 
     fn is_empty(&self) -> bool {
-        self.free_slots.len() == self.num_tiles as usize
+        self.covered_by.is_none() && self.free_slots.len() == self.num_tiles as usize
     }
 
     fn clear(&mut self) {
         self.free_slots.clear();
         self.num_tiles = 0;
         self.tile_size = size2(0, 0);
+        self.covered_by = None;
     }
 }
 
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct TiledAllocator {
     regions: Vec<TiledRegion>,
     size: Size,
     layers: u16,
     tile_sizes: TileSizes,
+    /// Number of region initializations per layer and tile size.
+    #[cfg_attr(feature = "serde", serde(default))]
+    initialized_regions: Vec<(u16, Size, u32)>,
 }
 
 impl TiledAllocator {
     pub fn new(size: Size, tile_sizes: TileSizes, layers: &[TiledAllocatorOptions]) -> Self {
+        TiledAllocator::with_slot_order(size, tile_sizes, layers, SlotOrder::DEFAULT)
+    }
+
+    pub fn with_slot_order(size: Size, tile_sizes: TileSizes, layers: &[TiledAllocatorOptions], slot_order: SlotOrder) -> Self {
 
         let mut regions = Vec::new();
 
@@ -108,13 +374,14 @@ impl TiledAllocator {
                 for x in 0..regions_x {
                     let index = regions.len() as u16;
                     regions.push(TiledRegion {
-                        free_slots: Vec::new(),
+                        free_slots: FreeSlots::new(slot_order),
                         tile_size: Size::new(0, 0),
                         size: options.region_size,
                         num_tiles: 0,
                         origin: point2(x, y),
                         index,
                         layer: layer as u16,
+                        covered_by: None,
                     });
                 }
             }
@@ -125,22 +392,28 @@ impl TiledAllocator {
             size,
             layers: layers.len() as u16,
             tile_sizes,
+            initialized_regions: Vec::new(),
         }
     }
 
     pub fn allocate(&mut self, size: Size) -> Option<ArrayAllocation> {
-        println!("allocate {:?}", size);
         let size = self.tile_sizes.get(size)?;
 
-        println!(" - tile size {:?}", size);
-
-        let mut empty_index = None;
+        // Among the empty regions, prefer the ones where the tiles waste the least space,
+        // then the smallest ones to keep large regions available for large tiles.
+        let mut empty_region: Option<((i32, i32), usize)> = None;
         for (idx, region) in self.regions.iter_mut().enumerate() {
-            if empty_index.is_none()
-                && region.is_empty()
+            if region.is_empty()
                 && region.size.width >= size.width
                 && region.size.height >= size.height {
-                empty_index = Some(idx);
+                let score = (region_waste(region.size, size), region.size.area());
+                let better = match empty_region {
+                    Some((best, _)) => score < best,
+                    None => true,
+                };
+                if better {
+                    empty_region = Some((score, idx));
+                }
             }
             if region.tile_size == size {
                 if let Some(alloc) = region.allocate() {
@@ -149,13 +422,101 @@ impl TiledAllocator {
             }
         }
 
-        println!(" - need to allocate a region {:?}", empty_index);
+        if let Some((_, idx)) = empty_region {
+            return self.init_region(idx, size);
+        }
+
+        self.allocate_spanning(size)
+    }
+
+    fn init_region(&mut self, idx: usize, tile_size: Size) -> Option<ArrayAllocation> {
+        let region = &mut self.regions[idx];
+        region.init(tile_size);
+
+        let layer = region.layer;
+        match self.initialized_regions.iter_mut().find(|(l, s, _)| *l == layer && *s == tile_size) {
+            Some((_, _, count)) => *count += 1,
+            None => self.initialized_regions.push((layer, tile_size, 1)),
+        }
+
+        self.regions[idx].allocate()
+    }
+
+    /// How each layer is used for each tile size, sorted by layer and tile size.
+    pub fn usage(&self) -> Vec<TileUsage> {
+        let mut usage: Vec<TileUsage> = self.initialized_regions.iter().map(|&(layer, tile_size, count)| TileUsage {
+            layer,
+            tile_size,
+            initialized_regions: count,
+            live_regions: 0,
+            live_tiles: 0,
+        }).collect();
+
+        for region in &self.regions {
+            if region.tile_size.is_empty() {
+                continue;
+            }
+
+            let idx = match usage.iter().position(|u| u.layer == region.layer && u.tile_size == region.tile_size) {
+                Some(idx) => idx,
+                None => {
+                    usage.push(TileUsage {
+                        layer: region.layer,
+                        tile_size: region.tile_size,
+                        initialized_regions: 0,
+                        live_regions: 0,
+                        live_tiles: 0,
+                    });
+                    usage.len() - 1
+                }
+            };
+            usage[idx].live_regions += 1;
+            usage[idx].live_tiles += region.num_allocated_tiles() as u32;
+        }
+
+        usage.sort_by_key(|u| (u.layer, u.tile_size.width, u.tile_size.height));
+
+        usage
+    }
+
+    /// Allocate a tile that is larger than the regions of its layer by claiming an
+    /// aligned block of adjacent empty regions.
+    fn allocate_spanning(&mut self, tile_size: Size) -> Option<ArrayAllocation> {
+        if tile_size.width > self.size.width || tile_size.height > self.size.height {
+            return None;
+        }
+
+        for idx in 0..self.regions.len() {
+            let region = &self.regions[idx];
+            let region_size = region.size;
+            if !region.is_empty() || (tile_size.width <= region_size.width && tile_size.height <= region_size.height) {
+                continue;
+            }
+
+            let nx = (tile_size.width as u32).div_ceil(region_size.width as u32) as i32;
+            let ny = (tile_size.height as u32).div_ceil(region_size.height as u32) as i32;
+            let regions_x = self.size.width / region_size.width;
+            let regions_y = self.size.height / region_size.height;
+            if region.origin.x % nx != 0
+                || region.origin.y % ny != 0
+                || region.origin.x + nx > regions_x
+                || region.origin.y + ny > regions_y {
+                continue;
+            }
+
+            // The regions of a layer are stored in rows.
+            let block: Vec<usize> = (0..ny)
+                .flat_map(|dy| (0..nx).map(move |dx| idx + (dy * regions_x + dx) as usize))
+                .collect();
+            if !block.iter().all(|&covered| self.regions[covered].is_empty()) {
+                continue;
+            }
+
+            for &covered in &block[1..] {
+                self.regions[covered].covered_by = Some(idx as u16);
+            }
 
-        if let Some(idx) = empty_index {
-            let region = &mut self.regions[idx];
-            println!(" init region {:?}", idx);
-            region.init(size);
-            return region.allocate();
+            return self.init_region(idx, tile_size);
         }
 
         None
@@ -170,24 +531,38 @@ impl TiledAllocator {
         region.free_slots.push((x, y));
 
         if region.is_empty() {
-            println!("region is empty");
-            region.free_slots.clear();
-            region.tile_size = Size::new(0, 0);
-            region.num_tiles = 0;
+            self.release_region(region_idx);
+        }
+    }
+
+    /// Reset a region, along with the regions covered by its tile if it spans several.
+    fn release_region(&mut self, idx: usize) {
+        if self.regions[idx].is_spanning() {
+            let anchor = Some(idx as u16);
+            for region in &mut self.regions {
+                if region.covered_by == anchor {
+                    region.covered_by = None;
+                }
+            }
         }
+
+        self.regions[idx].clear();
     }
 
     pub fn allocate_full_layer(&mut self) -> ArrayAllocation {
         let layer = self.layers;
+        self.layers += 1;
         let index = self.regions.len() as u16;
+        // The order doesn't matter with a single tile.
         self.regions.push(TiledRegion {
-            free_slots: Vec::new(),
-            tile_size: Size::new(0, 0),
+            free_slots: FreeSlots::new(SlotOrder::Stack),
+            tile_size: self.size,
             size: self.size,
             num_tiles: 1,
             origin: point2(0, 0),
             index,
             layer,
+            covered_by: None,
         });
 
         ArrayAllocation {
@@ -201,6 +576,144 @@ impl TiledAllocator {
         }
     }
 
+    /// Check the internal invariants of the allocator and panic if any of them
+    /// is violated.
+    ///
+    /// This is meant to help with debugging and testing, it is too slow to be
+    /// called often in production code.
+    pub fn validate(&self) {
+        for (idx, region) in self.regions.iter().enumerate() {
+            assert_eq!(region.index as usize, idx, "region {} has the wrong index", idx);
+
+            if region.tile_size.is_empty() {
+                assert_eq!(region.num_tiles, 0, "uninitialized region {} has tiles", idx);
+                assert!(region.free_slots.is_empty(), "uninitialized region {} has free slots", idx);
+
+                if let Some(anchor) = region.covered_by {
+                    let anchor = &self.regions[anchor as usize];
+                    assert!(anchor.is_spanning() && !anchor.is_empty(), "region {} is covered by a free region", idx);
+                    let tile = anchor.allocation((0, 0));
+                    let min = point2(region.origin.x * region.size.width, region.origin.y * region.size.height);
+                    assert!(
+                        anchor.layer == region.layer && tile.rectangle.contains(min),
+                        "region {} is outside of the tile covering it", idx,
+                    );
+                }
+                continue;
+            }
+
+            assert!(region.covered_by.is_none(), "covered region {} has tiles", idx);
+            if region.is_spanning() {
+                assert!(region.free_slots.is_empty(), "spanning region {} has free slots", idx);
+            }
+
+            let (n_tiles_x, n_tiles_y) = region.tile_grid();
+            assert_eq!(region.num_tiles as i32, n_tiles_x * n_tiles_y, "region {} has the wrong number of tiles", idx);
+            assert!(region.free_slots.len() <= region.num_tiles as usize, "region {} has too many free slots", idx);
+
+            let mut seen = vec![false; region.num_tiles as usize];
+            for (x, y) in region.free_slots.iter() {
+                assert!((x as i32) < n_tiles_x && (y as i32) < n_tiles_y, "free slot {:?} is out of region {}", (x, y), idx);
+                let slot = y as usize * n_tiles_x as usize + x as usize;
+                assert!(!seen[slot], "free slot {:?} of region {} is duplicated", (x, y), idx);
+                seen[slot] = true;
+            }
+        }
+    }
+
+    /// Invoke a callback with the layer and rectangle of each allocated tile.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(u16, &Rectangle),
+    {
+        for region in &self.regions {
+            region.for_each_allocation(|alloc| callback(alloc.layer, &alloc.rectangle));
+        }
+    }
+
+    /// Pick the region that is the cheapest to reinitialize so that an allocation of the
+    /// provided size can succeed, when `allocate` fails because all regions that are large
+    /// enough are in use with other tile sizes.
+    ///
+    /// `age` is called with the live allocations of the candidate regions and returns how
+    /// long ago each of them was last used. Regions with the fewest live tiles are
+    /// preferred, then the ones whose most recently used tile is the oldest.
+    ///
+    /// The returned allocations must be evicted or relocated by the caller, after which
+    /// `evict_region` reinitializes the region.
+    ///
+    /// Only tiles that fit in a single region are considered. A tile that spans several
+    /// regions can be evicted to make room for smaller ones.
+    pub fn select_region_to_evict(&self, size: Size, age: &mut dyn FnMut(AllocId) -> u32) -> Option<Eviction> {
+        let tile_size = self.tile_sizes.get(size)?;
+
+        let mut best: Option<((usize, std::cmp::Reverse<u32>), usize)> = None;
+        for (idx, region) in self.regions.iter().enumerate() {
+            if region.tile_size == tile_size
+                || region.is_empty()
+                || region.covered_by.is_some()
+                || region.size.width < tile_size.width
+                || region.size.height < tile_size.height {
+                continue;
+            }
+
+            let mut youngest = u32::MAX;
+            region.for_each_allocation(|alloc| youngest = youngest.min(age(alloc.id)));
+
+            let score = (region.num_allocated_tiles(), std::cmp::Reverse(youngest));
+            let better = match best {
+                Some((best_score, _)) => score < best_score,
+                None => true,
+            };
+            if better {
+                best = Some((score, idx));
+            }
+        }
+
+        let region = &self.regions[best?.1];
+        let mut allocations = Vec::with_capacity(region.num_allocated_tiles());
+        region.for_each_allocation(|alloc| allocations.push(alloc));
+
+        Some(Eviction {
+            region: region.index,
+            layer: region.layer,
+            allocations,
+        })
+    }
+
+    /// Release all allocations of a region so that it can be used with another tile size.
+    ///
+    /// The ids of the allocations of the region become invalid.
+    pub fn evict_region(&mut self, region: u16) {
+        self.release_region(region as usize);
+    }
+
+    /// Allocate a tile, evicting the region selected by `select_region_to_evict` if there
+    /// is no space otherwise.
+    ///
+    /// Returns the allocation and the allocations that were evicted, if any.
+    pub fn allocate_evicting(
+        &mut self,
+        size: Size,
+        age: &mut dyn FnMut(AllocId) -> u32,
+    ) -> Option<(ArrayAllocation, Vec<ArrayAllocation>)> {
+        if let Some(alloc) = self.allocate(size) {
+            return Some((alloc, Vec::new()));
+        }
+
+        let eviction = self.select_region_to_evict(size, age)?;
+        self.evict_region(eviction.region);
+
+        self.allocate(size).map(|alloc| (alloc, eviction.allocations))
+    }
+
+    /// The area covered by allocated tiles, summed over all layers.
+    pub fn allocated_space(&self) -> i32 {
+        self.regions.iter()
+            .map(|region| (region.num_tiles as i32 - region.free_slots.len() as i32) * region.tile_size.area())
+            .sum()
+    }
+
     pub fn num_layers(&self) -> u16 {
         self.layers
     }
@@ -226,7 +739,7 @@ impl TiledAllocator {
     }
 }
 
-#[derive(Copy, Clone, Debug,)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TileSizes {
     WrDefault,
@@ -242,6 +755,13 @@ impl TileSizes {
     }
 }
 
+/// The area of a region that tiles of the provided size can't cover.
+fn region_waste(region_size: Size, tile_size: Size) -> i32 {
+    let n_tiles = (region_size.width / tile_size.width) * (region_size.height / tile_size.height);
+
+    region_size.area() - n_tiles * tile_size.area()
+}
+
 pub fn wr_default_tile_size(size: Size) -> Option<Size> {
     fn quantize_dimension(size: i32) -> Option<i32> {
         match size {
@@ -315,19 +835,39 @@ use svg_fmt::*;
 
 /// Dump a visual representation of the atlas in SVG format.
 pub fn dump_svg(atlas: &TiledAllocator, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    dump_svg_with_options(atlas, &SvgOptions { captions: false, .. SvgOptions::DEFAULT }, &[], output)
+}
+
+/// Dump a visual representation of the atlas in SVG format, with the provided allocations
+/// drawn on top of it.
+pub fn dump_svg_with_options(
+    atlas: &TiledAllocator,
+    options: &SvgOptions,
+    allocations: &[SvgAllocation],
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
     let (layers_in_x, layers_in_y) = arrange_layers(atlas.num_layers() as usize);
     let spacing = 5.0;
+    let caption_height = if options.captions { svg::CAPTION_HEIGHT } else { 0.0 };
+
+    let h = (atlas.size().height as f32 + spacing) * layers_in_y as f32 - spacing;
 
     writeln!(
         output,
         "{}",
         BeginSvg {
             w: (atlas.size().width as f32 + spacing) * layers_in_x as f32 - spacing,
-            h: (atlas.size().height as f32 + spacing) * layers_in_y as f32 - spacing,
+            h: h + caption_height,
         }
     )?;
 
-    dump_into_svg(atlas, None, output)?;
+    dump_into_svg_with_options(atlas, None, options, allocations, output)?;
+
+    if options.captions {
+        let total_space = atlas.size().area() as i64 * atlas.num_layers() as i64;
+        let occupancy = svg::occupancy(atlas.allocated_space() as i64, total_space);
+        svg::write_caption(0.0, h, &format!("{:.1}% occupied", occupancy), output)?;
+    }
 
     writeln!(output, "{}", EndSvg)
 }
@@ -337,31 +877,49 @@ pub fn dump_svg(atlas: &TiledAllocator, output: &mut dyn std::io::Write) -> std:
 ///
 /// If a rectangle is provided, translate and scale the output to fit it.
 pub fn dump_into_svg(atlas: &TiledAllocator, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    dump_into_svg_with_options(atlas, rect, &SvgOptions::DEFAULT, &[], output)
+}
+
+/// Same as `dump_into_svg`, with the provided allocations drawn on top of their layers.
+pub fn dump_into_svg_with_options(
+    atlas: &TiledAllocator,
+    rect: Option<&Rectangle>,
+    options: &SvgOptions,
+    allocations: &[SvgAllocation],
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
     let layer_width = atlas.size().width as f32;
-    let layer_height = atlas.size().height as f32; 
+    let layer_height = atlas.size().height as f32;
 
     let (layers_in_x, layers_in_y) = arrange_layers(atlas.num_layers() as usize);
 
     let spacing = 5.0;
     let (sx, sy, x0, y0) = if let Some(rect) = rect {
-        let n_layers = layers_in_x.max(layers_in_y) as f32; 
+        let n_layers = layers_in_x.max(layers_in_y) as f32;
         (
-            rect.size().width as f32 / ((layer_width as f32 + spacing) * n_layers - spacing),
-            rect.size().height as f32 / ((layer_height as f32 + spacing) * n_layers - spacing),
+            rect.size().width as f32 / ((layer_width + spacing) * n_layers - spacing),
+            rect.size().height as f32 / ((layer_height + spacing) * n_layers - spacing),
             rect.min.x as f32,
             rect.min.y as f32,
         )
     } else {
-        (1.0, 1.0, 0.0, 0.0)        
+        (1.0, 1.0, 0.0, 0.0)
     };
 
     let spacing_x = spacing * sx;
     let spacing_y = spacing * sy;
 
     let layer_width = layer_width * sx;
-    let layer_height = layer_height * sy; 
+    let layer_height = layer_height * sy;
+
+    let allocated_color = svg::allocated_color(options, svg::AllocatorKind::Tiled);
 
     for region in &atlas.regions {
+        // Drawn as part of the tile that covers it.
+        if region.covered_by.is_some() {
+            continue;
+        }
+
         let region_width = region.size.width as f32 * sx;
         let region_height = region.size.height as f32 * sy;
 
@@ -374,8 +932,7 @@ pub fn dump_into_svg(atlas: &TiledAllocator, rect: Option<&Rectangle>, output: &
         let slot_height = region.tile_size.height as f32 * sy;
 
         if !region.is_empty() {
-            let n_tiles_x = region.size.width / region.tile_size.width;
-            let n_tiles_y = region.size.height / region.tile_size.height;
+            let (n_tiles_x, n_tiles_y) = region.tile_grid();
 
             // First pretend all slots are allocated and overwrite free slots
             // with gray rectangles.
@@ -387,13 +944,13 @@ pub fn dump_into_svg(atlas: &TiledAllocator, rect: Option<&Rectangle>, output: &
                         output,
                         r#"    {}"#,
                         rectangle(x, y, slot_width, slot_height)
-                            .fill(rgb(70, 70, 180))
+                            .fill(allocated_color)
                             .stroke(Stroke::Color(black(), 1.0))
                     )?;
                 }
             }
 
-            for &(x, y) in &region.free_slots {
+            for (x, y) in region.free_slots.iter() {
                 let x = region_x + x as f32 * region.tile_size.width as f32 * sx;
                 let y = region_y + y as f32 * region.tile_size.height as f32 * sy;
                 writeln!(
@@ -415,7 +972,22 @@ pub fn dump_into_svg(atlas: &TiledAllocator, rect: Option<&Rectangle>, output: &
         }
     }
 
-    Ok(())
+    let first_layer = Rectangle {
+        min: point2(x0, y0).to_i32(),
+        max: point2(x0 + layer_width, y0 + layer_height).to_i32(),
+    };
+
+    // Move the allocations from their layer into the coordinates of the first one, layers
+    // are `spacing` apart in atlas units.
+    let layer_offset = |layer: u16| euclid::vec2(
+        (layer as usize % layers_in_x) as i32 * (atlas.size().width + spacing as i32),
+        (layer as usize / layers_in_x) as i32 * (atlas.size().height + spacing as i32),
+    );
+    let allocations: Vec<SvgAllocation> = allocations.iter()
+        .map(|alloc| SvgAllocation { rectangle: alloc.rectangle.translate(layer_offset(alloc.layer)), .. *alloc })
+        .collect();
+
+    svg::dump_allocations_into_svg(atlas.size(), Some(&first_layer), svg::AllocatorKind::Tiled, options, &allocations, output)
 }
 
 fn arrange_layers(num_layers: usize) -> (usize, usize) {
@@ -479,3 +1051,226 @@ fn simple_2() {
 
     //dump_svg(&atlas, &mut std::fs::File::create("test8.svg").expect("!!"));
 }
+
+#[test]
+fn random_operations() {
+    for &(tile_sizes, region_size) in &[
+        (TileSizes::WrDefault, size2(512, 512)),
+        (TileSizes::WrGlyphs, size2(128, 128)),
+    ] {
+        let mut atlas = TiledAllocator::new(size2(1024, 1024), tile_sizes, &[
+            TiledAllocatorOptions { region_size },
+        ]);
+
+        crate::check_random_operations(&mut atlas, 1, 2000, size2(100, 100), &|atlas, live| {
+            atlas.validate();
+            let live_tiles: usize = atlas.regions.iter()
+                .map(|region| region.num_tiles as usize - region.free_slots.len())
+                .sum();
+            assert_eq!(live_tiles, live);
+        });
+    }
+}
+
+#[test]
+fn random_operations_multi_layer() {
+    let mut atlas = TiledAllocator::new(size2(512, 512), TileSizes::WrDefault, &[
+        TiledAllocatorOptions { region_size: size2(64, 64) },
+        TiledAllocatorOptions { region_size: size2(128, 128) },
+        TiledAllocatorOptions { region_size: size2(256, 256) },
+    ]);
+
+    crate::check_random_operations(&mut atlas, 7, 2000, size2(200, 200), &|atlas, _| atlas.validate());
+}
+
+#[test]
+fn evict_region() {
+    let mut atlas = TiledAllocator::new(size2(64, 64), TileSizes::WrDefault, &[
+        TiledAllocatorOptions { region_size: size2(32, 32) },
+    ]);
+
+    let small: Vec<ArrayAllocation> = (0..6).map(|_| atlas.allocate(size2(16, 16)).unwrap()).collect();
+    let large = [
+        atlas.allocate(size2(32, 32)).unwrap(),
+        atlas.allocate(size2(32, 32)).unwrap(),
+    ];
+    assert!(atlas.allocate(size2(32, 32)).is_none());
+
+    // The region with two small tiles is cheaper than the one with four.
+    let ids = |allocs: &[ArrayAllocation]| {
+        let mut ids: Vec<u32> = allocs.iter().map(|alloc| alloc.id.0).collect();
+        ids.sort_unstable();
+        ids
+    };
+
+    let mut age = |_| 0;
+    let eviction = atlas.select_region_to_evict(size2(32, 32), &mut age).unwrap();
+    assert_eq!(ids(&eviction.allocations), ids(&small[4..]));
+
+    // With equal live tile counts, the region whose tiles were used least recently wins.
+    let old = &small[..4];
+    let mut age = |id| if old.iter().any(|alloc| alloc.id == id) { 100 } else { 1 };
+    atlas.deallocate(small[0].id);
+    atlas.deallocate(small[1].id);
+    let eviction = atlas.select_region_to_evict(size2(32, 32), &mut age).unwrap();
+    assert_eq!(ids(&eviction.allocations), ids(&small[2..4]));
+
+    let (alloc, evicted) = atlas.allocate_evicting(size2(32, 32), &mut age).unwrap();
+    assert_eq!(evicted, eviction.allocations);
+    assert_eq!(alloc.rectangle.size(), size2(32, 32));
+    atlas.validate();
+
+    for alloc in &small[4..] {
+        atlas.deallocate(alloc.id);
+    }
+    for alloc in large.iter().chain(Some(&alloc)) {
+        atlas.deallocate(alloc.id);
+    }
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn spanning_regions() {
+    let mut atlas = TiledAllocator::new(size2(512, 512), TileSizes::WrGlyphs, &[
+        TiledAllocatorOptions { region_size: size2(128, 128) },
+    ]);
+
+    let glyph = atlas.allocate(size2(10, 10)).unwrap();
+    let a = atlas.allocate(size2(200, 200)).unwrap();
+    assert_eq!(a.rectangle, Rectangle { min: point2(256, 0), max: point2(512, 256) });
+    let b = atlas.allocate(size2(150, 250)).unwrap();
+    assert_eq!(b.rectangle.min, point2(0, 256));
+    atlas.validate();
+
+    // The top-left block is not available because of the glyph.
+    let c = atlas.allocate(size2(256, 256)).unwrap();
+    assert_eq!(c.rectangle.min, point2(256, 256));
+    assert!(atlas.allocate(size2(256, 256)).is_none());
+    assert!(atlas.allocate(size2(512, 512)).is_none());
+    assert_eq!(atlas.allocated_space(), 16 * 16 + 3 * 256 * 256);
+
+    let mut svg = Vec::new();
+    dump_svg(&atlas, &mut svg).unwrap();
+    assert!(String::from_utf8(svg).unwrap().contains(r#"width="256" height="256""#));
+
+    for alloc in &[glyph, a, b, c] {
+        atlas.deallocate(alloc.id);
+        atlas.validate();
+    }
+    assert!(atlas.is_empty());
+
+    let full = atlas.allocate(size2(500, 400)).unwrap();
+    assert_eq!(full.rectangle.size(), size2(512, 512));
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+
+    // Evicting the spanning tile releases all of its regions.
+    let eviction = atlas.select_region_to_evict(size2(1, 1), &mut |_| 0).unwrap();
+    assert_eq!(eviction.allocations, vec![full]);
+    atlas.evict_region(eviction.region);
+    atlas.validate();
+    assert!(atlas.is_empty());
+
+    // Some of the random items are large enough to span several regions.
+    crate::check_random_operations(&mut atlas, 3, 1000, size2(512, 512), &|atlas, _| atlas.validate());
+}
+
+#[test]
+fn slot_orders() {
+    for &order in &[SlotOrder::Stack, SlotOrder::RowMajor, SlotOrder::Morton] {
+        let mut atlas = TiledAllocator::with_slot_order(size2(64, 64), TileSizes::WrDefault, &[
+            TiledAllocatorOptions { region_size: size2(64, 64) },
+        ], order);
+
+        let allocs: Vec<ArrayAllocation> = (0..16).map(|_| atlas.allocate(size2(16, 16)).unwrap()).collect();
+        let origins: Vec<Point> = allocs.iter().map(|alloc| alloc.rectangle.min / 16).collect();
+        let expected = match order {
+            SlotOrder::Stack | SlotOrder::RowMajor => [point2(0, 0), point2(1, 0), point2(2, 0), point2(3, 0), point2(0, 1)],
+            SlotOrder::Morton => [point2(0, 0), point2(1, 0), point2(0, 1), point2(1, 1), point2(2, 0)],
+        };
+        assert_eq!(&origins[..5], &expected);
+
+        atlas.deallocate(allocs[2].id);
+        atlas.deallocate(allocs[9].id);
+        atlas.validate();
+
+        // The stack reuses the last freed tile, the other policies the lowest one.
+        let alloc = atlas.allocate(size2(16, 16)).unwrap();
+        let expected = if order == SlotOrder::Stack { allocs[9] } else { allocs[2] };
+        assert_eq!(alloc.rectangle, expected.rectangle);
+        atlas.validate();
+    }
+
+    for x in 0..=255u8 {
+        assert_eq!(compact_bits(spread_bits(x)), x);
+        assert_eq!(slot_at(slot_index((x, 255 - x), true, 0), true, 0), (x, 255 - x));
+    }
+}
+
+#[test]
+fn random_operations_slot_orders() {
+    for &order in &[SlotOrder::RowMajor, SlotOrder::Morton] {
+        let mut atlas = TiledAllocator::with_slot_order(size2(1024, 1024), TileSizes::WrGlyphs, &[
+            TiledAllocatorOptions { region_size: size2(96, 128) },
+        ], order);
+
+        crate::check_random_operations(&mut atlas, 1, 2000, size2(100, 100), &|atlas, live| {
+            atlas.validate();
+            let live_tiles: usize = atlas.regions.iter()
+                .map(|region| region.num_tiles as usize - region.free_slots.len())
+                .sum();
+            assert_eq!(live_tiles, live);
+        });
+    }
+}
+
+#[test]
+fn best_fit_regions() {
+    let mut atlas = TiledAllocator::new(size2(384, 384), TileSizes::WrDefault, &[
+        TiledAllocatorOptions { region_size: size2(96, 96) },
+        TiledAllocatorOptions { region_size: size2(128, 128) },
+        TiledAllocatorOptions { region_size: size2(64, 64) },
+    ]);
+
+    // 64px tiles waste the least in the 128px and 64px regions, the smallest one wins.
+    assert_eq!(atlas.allocate(size2(64, 64)).unwrap().layer, 2);
+    // 32px tiles divide all regions evenly.
+    assert_eq!(atlas.allocate(size2(30, 30)).unwrap().layer, 2);
+    // 96px tiles don't exist, 128px ones only fit in the 128px regions.
+    assert_eq!(atlas.allocate(size2(90, 90)).unwrap().layer, 1);
+    // The region with 32px tiles has space left.
+    let a = atlas.allocate(size2(20, 20)).unwrap();
+    assert_eq!(a.layer, 2);
+    atlas.deallocate(a.id);
+
+    let usage = atlas.usage();
+    assert_eq!(usage, vec![
+        TileUsage { layer: 1, tile_size: size2(128, 128), initialized_regions: 1, live_regions: 1, live_tiles: 1 },
+        TileUsage { layer: 2, tile_size: size2(32, 32), initialized_regions: 1, live_regions: 1, live_tiles: 1 },
+        TileUsage { layer: 2, tile_size: size2(64, 64), initialized_regions: 1, live_regions: 1, live_tiles: 1 },
+    ]);
+
+    atlas.clear();
+    let usage = atlas.usage();
+    assert_eq!(usage.len(), 3);
+    assert!(usage.iter().all(|u| u.live_regions == 0 && u.live_tiles == 0 && u.initialized_regions == 1));
+}
+
+#[test]
+fn svg_allocations_on_layers() {
+    let mut atlas = TiledAllocator::new(size2(128, 128), TileSizes::WrDefault, &[
+        TiledAllocatorOptions { region_size: size2(32, 32) },
+        TiledAllocatorOptions { region_size: size2(128, 128) },
+    ]);
+    let alloc = atlas.allocate(size2(100, 100)).unwrap();
+    assert_eq!(alloc.layer, 1);
+
+    let allocations = [SvgAllocation { rectangle: alloc.rectangle, layer: alloc.layer, label: Some("a"), age: 0 }];
+    let mut svg = Vec::new();
+    dump_svg_with_options(&atlas, &SvgOptions::DEFAULT, &allocations, &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    // The label is centered on the rectangle in the second layer, to the right of the first.
+    let x = 128 + 5 + alloc.rectangle.min.x + alloc.rectangle.width() / 2;
+    let label = svg.lines().find(|line| line.contains("> a </text>")).unwrap();
+    assert!(label.contains(&format!("x=\"{}\"", x)), "{}", label);
+}
@@ -0,0 +1,312 @@
+//! A bump allocator for atlases that are cleared every frame.
+//!
+//! Rectangles are packed on shelves from the top-left corner: each allocation goes to the
+//! right of the previous one, and a new shelf is started below when the current one is
+//! full. Individual allocations can't be freed, the whole atlas is reset with `clear`.
+
+use crate::{AllocId, Rectangle, Size, point2, size2};
+use crate::svg::{self, SvgAllocation, SvgOptions};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransientAllocatorOptions {
+    /// Align item sizes to a multiple of this alignment.
+    ///
+    /// Default value: [1, 1] (no alignment).
+    pub alignment: Size,
+}
+
+impl TransientAllocatorOptions {
+    pub const DEFAULT: Self = TransientAllocatorOptions {
+        alignment: size2(1, 1),
+    };
+}
+
+impl Default for TransientAllocatorOptions {
+    fn default() -> Self {
+        TransientAllocatorOptions::DEFAULT
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Shelf {
+    y: i32,
+    height: i32,
+    /// Width used by the allocations of this shelf.
+    width: i32,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransientAllocator {
+    size: Size,
+    alignment: Size,
+    /// Only the last shelf receives new allocations.
+    shelves: Vec<Shelf>,
+    num_allocations: u32,
+    high_water_mark: Size,
+    previous_high_water_mark: Size,
+}
+
+impl TransientAllocator {
+    pub fn new(size: Size) -> Self {
+        TransientAllocator::with_options(size, &TransientAllocatorOptions::DEFAULT)
+    }
+
+    pub fn with_options(size: Size, options: &TransientAllocatorOptions) -> Self {
+        assert!(options.alignment.width > 0 && options.alignment.height > 0);
+
+        TransientAllocator {
+            size,
+            alignment: options.alignment,
+            shelves: Vec::new(),
+            num_allocations: 0,
+            high_water_mark: size2(0, 0),
+            previous_high_water_mark: size2(0, 0),
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Allocate a rectangle in the atlas.
+    pub fn allocate(&mut self, mut size: Size) -> Option<(AllocId, Rectangle)> {
+        if size.is_empty() {
+            return None;
+        }
+
+        adjust_size(self.alignment.width, &mut size.width);
+        adjust_size(self.alignment.height, &mut size.height);
+
+        if size.width > self.size.width || size.height > self.size.height {
+            return None;
+        }
+
+        let needs_new_shelf = match self.shelves.last() {
+            Some(shelf) => shelf.width + size.width > self.size.width,
+            None => true,
+        };
+
+        if needs_new_shelf {
+            let y = self.shelves.last().map(|shelf| shelf.y + shelf.height).unwrap_or(0);
+            self.shelves.push(Shelf { y, height: 0, width: 0 });
+        }
+
+        // The last shelf can grow since there is nothing below it.
+        let shelf = self.shelves.last_mut().unwrap();
+        if shelf.y + size.height > self.size.height {
+            if shelf.width == 0 {
+                self.shelves.pop();
+            }
+            return None;
+        }
+
+        let origin = point2(shelf.width, shelf.y);
+        shelf.width += size.width;
+        shelf.height = shelf.height.max(size.height);
+
+        self.high_water_mark = size2(
+            self.high_water_mark.width.max(shelf.width),
+            self.high_water_mark.height.max(shelf.y + shelf.height),
+        );
+
+        let id = AllocId(self.num_allocations);
+        self.num_allocations += 1;
+
+        Some((id, Rectangle { min: origin, max: origin + size.to_vector() }))
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// The new size can't be smaller than the current one in either dimension.
+    pub fn grow(&mut self, new_size: Size) {
+        assert!(new_size.width >= self.size.width && new_size.height >= self.size.height);
+        self.size = new_size;
+    }
+
+    /// Does nothing, the space is only reclaimed by `clear`.
+    pub fn deallocate(&mut self, _id: AllocId) {}
+
+    /// Release all allocations and start a new frame.
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+        self.num_allocations = 0;
+        self.previous_high_water_mark = self.high_water_mark;
+        self.high_water_mark = size2(0, 0);
+    }
+
+    /// Whether nothing was allocated since the last `clear`.
+    pub fn is_empty(&self) -> bool {
+        self.num_allocations == 0
+    }
+
+    /// Number of allocations since the last `clear`.
+    pub fn num_allocations(&self) -> u32 {
+        self.num_allocations
+    }
+
+    /// The extent of the allocations since the last `clear`.
+    ///
+    /// A render target of this size would have been enough for the current frame.
+    pub fn high_water_mark(&self) -> Size {
+        self.high_water_mark
+    }
+
+    /// The high-water mark of the frame before the last `clear`.
+    pub fn previous_high_water_mark(&self) -> Size {
+        self.previous_high_water_mark
+    }
+
+    /// The area of the shelves that is used by allocations, including the space under
+    /// items that are shorter than their shelf.
+    pub fn allocated_space(&self) -> i32 {
+        self.shelves.iter().map(|shelf| shelf.width * shelf.height).sum()
+    }
+
+    /// Invoke a callback with the used part of each shelf.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for shelf in &self.shelves {
+            if shelf.width > 0 {
+                callback(&Rectangle {
+                    min: point2(0, shelf.y),
+                    max: point2(shelf.width, shelf.y + shelf.height),
+                });
+            }
+        }
+    }
+}
+
+fn adjust_size(alignment: i32, size: &mut i32) {
+    let rem = *size % alignment;
+    if rem > 0 {
+        *size += alignment - rem;
+    }
+}
+
+/// Dump a visual representation of the atlas in SVG format.
+pub fn dump_svg(atlas: &TransientAllocator, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    use svg_fmt::*;
+
+    writeln!(
+        output,
+        "{}",
+        BeginSvg {
+            w: atlas.size.width as f32,
+            h: atlas.size.height as f32,
+        }
+    )?;
+
+    dump_into_svg(atlas, None, output)?;
+
+    writeln!(output, "{}", EndSvg)
+}
+
+/// Dump a visual representation of the atlas in SVG, omitting the beginning and end of the
+/// SVG document, so that it can be included in a larger document.
+///
+/// If a rectangle is provided, translate and scale the output to fit it.
+pub fn dump_into_svg(atlas: &TransientAllocator, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    dump_into_svg_with_options(atlas, rect, &SvgOptions::DEFAULT, &[], output)
+}
+
+/// Same as `dump_into_svg`, with the provided allocations drawn on top of the atlas.
+pub fn dump_into_svg_with_options(
+    atlas: &TransientAllocator,
+    rect: Option<&Rectangle>,
+    options: &SvgOptions,
+    allocations: &[SvgAllocation],
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    use svg_fmt::*;
+
+    let size = atlas.size;
+    let (sx, sy, tx, ty) = if let Some(rect) = rect {
+        (
+            rect.size().width as f32 / size.width as f32,
+            rect.size().height as f32 / size.height as f32,
+            rect.min.x as f32,
+            rect.min.y as f32,
+        )
+    } else {
+        (1.0, 1.0, 0.0, 0.0)
+    };
+
+    writeln!(
+        output,
+        r#"    {}"#,
+        rectangle(tx, ty, size.width as f32 * sx, size.height as f32 * sy)
+            .fill(rgb(40, 40, 40))
+            .stroke(Stroke::Color(black(), 1.0))
+    )?;
+
+    let allocated_color = svg::allocated_color(options, svg::AllocatorKind::Transient);
+    let mut result = Ok(());
+    atlas.for_each_allocated_rectangle(|shelf| {
+        if result.is_ok() {
+            result = writeln!(
+                output,
+                r#"    {}"#,
+                rectangle(
+                    shelf.min.x as f32 * sx + tx,
+                    shelf.min.y as f32 * sy + ty,
+                    shelf.width() as f32 * sx,
+                    shelf.height() as f32 * sy,
+                )
+                .fill(allocated_color)
+                .stroke(Stroke::Color(black(), 1.0))
+            );
+        }
+    });
+    result?;
+
+    // Outline the high-water mark.
+    let mark = atlas.high_water_mark;
+    writeln!(
+        output,
+        r#"    {}"#,
+        rectangle(tx, ty, mark.width as f32 * sx, mark.height as f32 * sy)
+            .fill(Fill::None)
+            .stroke(Stroke::Color(rgb(250, 200, 40), 1.0))
+    )?;
+
+    svg::dump_allocations_into_svg(size, rect, svg::AllocatorKind::Transient, options, allocations, output)
+}
+
+#[test]
+fn bump() {
+    let mut atlas = TransientAllocator::new(size2(100, 100));
+    assert!(atlas.is_empty());
+
+    let (_, a) = atlas.allocate(size2(60, 10)).unwrap();
+    let (_, b) = atlas.allocate(size2(40, 20)).unwrap();
+    let (_, c) = atlas.allocate(size2(10, 10)).unwrap();
+    assert_eq!(a.min, point2(0, 0));
+    assert_eq!(b.min, point2(60, 0));
+    // The first shelf is full, c goes below the tallest item.
+    assert_eq!(c.min, point2(0, 20));
+    assert_eq!(atlas.high_water_mark(), size2(100, 30));
+    assert_eq!(atlas.allocated_space(), 100 * 20 + 10 * 10);
+
+    assert!(atlas.allocate(size2(101, 1)).is_none());
+    assert!(atlas.allocate(size2(100, 80)).is_none());
+    let (_, d) = atlas.allocate(size2(100, 70)).unwrap();
+    assert_eq!(d.min, point2(0, 30));
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+
+    atlas.deallocate(AllocId(0));
+    assert!(!atlas.is_empty());
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+    assert_eq!(atlas.high_water_mark(), size2(0, 0));
+    assert_eq!(atlas.previous_high_water_mark(), size2(100, 100));
+    assert_eq!(atlas.allocate(size2(5, 5)).unwrap().1.min, point2(0, 0));
+
+    let mut aligned = TransientAllocator::with_options(size2(64, 64), &TransientAllocatorOptions { alignment: size2(8, 4) });
+    assert_eq!(aligned.allocate(size2(3, 3)).unwrap().1.size(), size2(8, 4));
+}
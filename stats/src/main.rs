@@ -236,6 +236,17 @@ fn main() {
                 .required(false)
             )
         )
+        .subcommand(
+            SubCommand::with_name("suggest")
+            .about("Print shelf allocator options that suit the recorded sizes")
+            .arg(Arg::with_name("ATLAS")
+                .short("-a")
+                .long("atlas")
+                .help("Input texture atlas file.")
+                .value_name("ATLAS")
+                .takes_value(true)
+             )
+        )
         .subcommand(
             SubCommand::with_name("list")
             .about("List the allocations and free rectangles in the atlas")
@@ -263,6 +274,8 @@ fn main() {
         svg(&cmd);
     } else if let Some(cmd) = matches.subcommand_matches("print") {
         print(&cmd);
+    } else if let Some(cmd) = matches.subcommand_matches("suggest") {
+        suggest(cmd);
     } else if let Some(_cmd) = matches.subcommand_matches("list") {
         //list(&cmd);
     }
@@ -333,6 +346,16 @@ fn print(args: &ArgMatches) {
     session.stats.print();
 }
 
+fn suggest(args: &ArgMatches) {
+    let session = read_atlas(args);
+
+    let options = session.stats.suggest_shelf_options();
+    let serialized: String =
+        ron::ser::to_string_pretty(&options, ron::ser::PrettyConfig::default()).unwrap();
+
+    println!("{}", serialized);
+}
+
 fn deallocate(_args: &ArgMatches) {}
 
 fn svg(_args: &ArgMatches) {}